@@ -0,0 +1,328 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+use web_sys::AudioContext;
+
+use crate::get_bus;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Breakpoint {
+    /// Seconds into the transport
+    pub time: f64,
+    pub value: f32,
+}
+
+/// A control's recorded values, sorted by time and linearly interpolated between
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Lane {
+    pub points: Vec<Breakpoint>,
+}
+
+impl Lane {
+    pub fn at(&self, time: f64) -> Option<f32> {
+        let first = self.points.first()?;
+        if time <= first.time {
+            return Some(first.value);
+        }
+        for pair in self.points.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            if time <= b.time {
+                if b.time <= a.time {
+                    return Some(b.value);
+                }
+                let t = ((time - a.time) / (b.time - a.time)) as f32;
+                return Some(a.value + (b.value - a.value) * t);
+            }
+        }
+        self.points.last().map(|p| p.value)
+    }
+
+    /// Writes `value` at `time`, replacing whatever was recorded after `since`, the time of
+    /// the previous write this pass, which is kept
+    fn record(&mut self, since: Option<f64>, time: f64, value: f32) {
+        match since {
+            Some(since) if since < time => self.points.retain(|p| p.time <= since || p.time > time),
+            _ => self.points.retain(|p| p.time != time),
+        }
+        let idx = self.points.iter().position(|p| p.time > time).unwrap_or(self.points.len());
+        self.points.insert(idx, Breakpoint { time, value });
+    }
+
+    fn sort(&mut self) {
+        self.points.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap_or(std::cmp::Ordering::Equal));
+    }
+}
+
+/// A transport, with where it is in seconds
+struct Transport {
+    /// Context time at which the transport was at `position`
+    started_at: Option<f64>,
+    position: f64,
+}
+
+impl Transport {
+    fn position(&self, now: f64) -> f64 {
+        match self.started_at {
+            Some(started_at) => self.position + now - started_at,
+            None => self.position,
+        }
+    }
+}
+
+struct State {
+    lanes: BTreeMap<String, Lane>,
+    transport: Transport,
+    armed: bool,
+    /// Controls recorded this pass, with when they were last written. They don't play back
+    /// until the transport stops, so they can be overwritten.
+    recording: HashMap<String, f64>,
+    /// Values last played back, so unchanged ones don't get triggered again
+    played: HashMap<String, f32>,
+}
+
+/// Records bus triggers against a transport into per-control lanes and plays them back.
+///
+/// Triggers are recorded while armed and playing, each control's lane being overwritten
+/// from the first value recorded on each pass. Playback happens on `tick`, at control rate.
+#[wasm_bindgen]
+pub struct Automation {
+    ctx: AudioContext,
+    state: Rc<RefCell<State>>,
+    /// Set while playing back, so played back values don't get recorded
+    replaying: Rc<Cell<bool>>,
+}
+
+#[wasm_bindgen]
+impl Automation {
+    #[wasm_bindgen(constructor)]
+    pub fn new(ctx: AudioContext) -> Automation {
+        let state = Rc::new(RefCell::new(State {
+            lanes: BTreeMap::new(),
+            transport: Transport { started_at: None, position: 0.0 },
+            armed: false,
+            recording: HashMap::new(),
+            played: HashMap::new(),
+        }));
+        let replaying = Rc::new(Cell::new(false));
+
+        let bus = unsafe { get_bus() };
+        let s = state.clone();
+        let r = replaying.clone();
+        let c = ctx.clone();
+        bus.listen(Some(Box::new(move |id, value| {
+            let mut state = s.borrow_mut();
+            if r.get() || !state.armed || state.transport.started_at.is_none() {
+                return;
+            }
+            let time = state.transport.position(c.current_time());
+            let since = state.recording.insert(id.to_string(), time);
+            state.lanes.entry(id.to_string()).or_default().record(since, time, value);
+        })));
+
+        Automation { ctx, state, replaying }
+    }
+
+    /// Starts the transport from where it is
+    #[wasm_bindgen]
+    pub fn play(&mut self) {
+        let mut state = self.state.borrow_mut();
+        if state.transport.started_at.is_none() {
+            state.transport.started_at = Some(self.ctx.current_time());
+            state.recording.clear();
+            state.played.clear();
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn stop(&mut self) {
+        let now = self.ctx.current_time();
+        let mut state = self.state.borrow_mut();
+        state.transport.position = state.transport.position(now);
+        state.transport.started_at = None;
+        state.recording.clear();
+    }
+
+    #[wasm_bindgen]
+    pub fn is_playing(&self) -> bool {
+        self.state.borrow().transport.started_at.is_some()
+    }
+
+    /// Moves the transport to `position` seconds, e.g. 0 to rewind
+    #[wasm_bindgen]
+    pub fn set_position(&mut self, position: f64) {
+        let now = self.ctx.current_time();
+        let mut state = self.state.borrow_mut();
+        state.transport.position = position.max(0.0);
+        if state.transport.started_at.is_some() {
+            state.transport.started_at = Some(now);
+        }
+        state.recording.clear();
+        state.played.clear();
+    }
+
+    #[wasm_bindgen]
+    pub fn get_position(&self) -> f64 {
+        self.state.borrow().transport.position(self.ctx.current_time())
+    }
+
+    /// Records bus triggers while the transport plays
+    #[wasm_bindgen]
+    pub fn arm(&mut self, armed: bool) {
+        let mut state = self.state.borrow_mut();
+        state.armed = armed;
+        state.recording.clear();
+    }
+
+    #[wasm_bindgen]
+    pub fn is_armed(&self) -> bool {
+        self.state.borrow().armed
+    }
+
+    /// Plays the lanes back onto their controls. Call it regularly, e.g. every frame.
+    #[wasm_bindgen]
+    pub fn tick(&mut self) {
+        let values: Vec<(String, f32)> = {
+            let mut state = self.state.borrow_mut();
+            if state.transport.started_at.is_none() {
+                return;
+            }
+            let position = state.transport.position(self.ctx.current_time());
+            let values: Vec<(String, f32)> = state.lanes.iter()
+                .filter(|(id, _)| !state.recording.contains_key(*id))
+                .filter_map(|(id, lane)| lane.at(position).map(|v| (id.clone(), v)))
+                .filter(|(id, v)| state.played.get(id) != Some(v))
+                .collect();
+            for (id, v) in &values {
+                state.played.insert(id.clone(), *v);
+            }
+            values
+        };
+
+        let bus = unsafe { get_bus() };
+        self.replaying.set(true);
+        for (id, v) in values {
+            bus.trigger(id, v);
+        }
+        self.replaying.set(false);
+    }
+
+    /// Ids of the controls with a lane
+    #[wasm_bindgen]
+    pub fn lanes(&self) -> js_sys::Array {
+        let ids = js_sys::Array::new();
+        for id in self.state.borrow().lanes.keys() {
+            ids.push(&JsValue::from(id.as_str()));
+        }
+        ids
+    }
+
+    /// Breakpoints of control `id`'s lane, as `[{time, value}]`
+    #[wasm_bindgen]
+    pub fn get_lane(&self, id: &str) -> Result<JsValue, JsValue> {
+        let state = self.state.borrow();
+        let lane = state.lanes.get(id).ok_or_else(|| JsValue::from(format!("No lane for {}", id)))?;
+        JsValue::from_serde(&lane.points).map_err(|e| e.to_string().into())
+    }
+
+    /// Replaces the breakpoints of control `id`'s lane with `[{time, value}]`
+    #[wasm_bindgen]
+    pub fn set_lane(&mut self, id: String, points: &JsValue) -> Result<(), JsValue> {
+        let points: Vec<Breakpoint> = points.into_serde().map_err(|e| JsValue::from(e.to_string()))?;
+        let mut lane = Lane { points };
+        lane.sort();
+        let mut state = self.state.borrow_mut();
+        state.played.remove(&id);
+        state.lanes.insert(id, lane);
+        Ok(())
+    }
+
+    #[wasm_bindgen]
+    pub fn remove_lane(&mut self, id: &str) {
+        self.state.borrow_mut().lanes.remove(id);
+    }
+
+    /// Every lane, as `{<control id>: {points}}`, to save along with a session
+    #[wasm_bindgen]
+    pub fn save(&self) -> Result<JsValue, JsValue> {
+        JsValue::from_serde(&self.state.borrow().lanes).map_err(|e| e.to_string().into())
+    }
+
+    #[wasm_bindgen]
+    pub fn load(&mut self, lanes: &JsValue) -> Result<(), JsValue> {
+        let mut lanes: BTreeMap<String, Lane> = lanes.into_serde().map_err(|e| JsValue::from(e.to_string()))?;
+        for lane in lanes.values_mut() {
+            lane.sort();
+        }
+        let mut state = self.state.borrow_mut();
+        state.lanes = lanes;
+        state.recording.clear();
+        state.played.clear();
+        Ok(())
+    }
+}
+
+impl Drop for Automation {
+    fn drop(&mut self) {
+        let bus = unsafe { get_bus() };
+        bus.listen(None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lane(points: &[(f64, f32)]) -> Lane {
+        Lane { points: points.iter().map(|&(time, value)| Breakpoint { time, value }).collect() }
+    }
+
+    fn times(lane: &Lane) -> Vec<f64> {
+        lane.points.iter().map(|p| p.time).collect()
+    }
+
+    #[test]
+    fn at_interpolates_between_breakpoints() {
+        let lane = lane(&[(1.0, 0.0), (3.0, 1.0), (3.0, 0.5), (4.0, 0.5)]);
+        assert_eq!(Lane::default().at(1.0), None);
+        assert_eq!(lane.at(0.0), Some(0.0));
+        assert_eq!(lane.at(2.0), Some(0.5));
+        assert_eq!(lane.at(2.5), Some(0.75));
+        assert_eq!(lane.at(3.0), Some(1.0));
+        assert_eq!(lane.at(3.5), Some(0.5));
+        assert_eq!(lane.at(10.0), Some(0.5));
+    }
+
+    #[test]
+    fn record_keeps_every_write_of_a_pass() {
+        let mut lane = Lane::default();
+        lane.record(None, 1.0, 0.1);
+        lane.record(Some(1.0), 2.0, 0.2);
+        lane.record(Some(2.0), 3.0, 0.3);
+        assert_eq!(times(&lane), vec![1.0, 2.0, 3.0]);
+        assert_eq!(lane.at(2.5), Some(0.25));
+    }
+
+    #[test]
+    fn record_overwrites_earlier_passes() {
+        let mut lane = lane(&[(0.5, 1.0), (1.0, 1.0), (1.5, 1.0), (2.5, 1.0), (4.0, 1.0)]);
+        lane.record(None, 1.0, 0.1);
+        lane.record(Some(1.0), 2.0, 0.2);
+        lane.record(Some(2.0), 3.0, 0.3);
+        assert_eq!(times(&lane), vec![0.5, 1.0, 2.0, 3.0, 4.0]);
+        let values: Vec<f32> = lane.points.iter().map(|p| p.value).collect();
+        assert_eq!(values, vec![1.0, 0.1, 0.2, 0.3, 1.0]);
+    }
+
+    #[test]
+    fn record_replaces_writes_at_the_same_time() {
+        let mut lane = Lane::default();
+        lane.record(None, 1.0, 0.1);
+        lane.record(Some(1.0), 1.0, 0.2);
+        assert_eq!(times(&lane), vec![1.0]);
+        assert_eq!(lane.at(1.0), Some(0.2));
+    }
+}
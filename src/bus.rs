@@ -1,23 +1,31 @@
 use std::collections::HashMap;
 use wasm_bindgen::prelude::*;
 
-use web_sys::console;
-
 use std::rc::Rc;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 
 pub struct EventBus {
     controls: Rc<RefCell<HashMap<String, Box<Fn(f32)>>>>,
+    voice_controls: Rc<RefCell<HashMap<String, Box<Fn(usize, f32)>>>>,
     last_value: Rc<RefCell<HashMap<String, f32>>>,
-    modulations: Rc<RefCell<HashMap<String, Vec<String>>>>
+    offsets: Rc<RefCell<HashMap<String, f32>>>,
+    modulations: Rc<RefCell<HashMap<String, Vec<String>>>>,
+    /// Told about every trigger made from outside the bus, e.g. to record them
+    listener: Rc<RefCell<Option<Box<Fn(&str, f32)>>>>,
+    /// How many triggers deep the bus currently is, as controls may trigger others
+    depth: Cell<usize>,
 }
 
 impl EventBus {
     pub fn new() -> EventBus {
         EventBus {
             controls: Rc::new(RefCell::new(HashMap::new())),
+            voice_controls: Rc::new(RefCell::new(HashMap::new())),
             last_value: Rc::new(RefCell::new(HashMap::new())),
-            modulations: Rc::new(RefCell::new(HashMap::new()))
+            offsets: Rc::new(RefCell::new(HashMap::new())),
+            modulations: Rc::new(RefCell::new(HashMap::new())),
+            listener: Rc::new(RefCell::new(None)),
+            depth: Cell::new(0),
         }
 
     }
@@ -31,6 +39,64 @@ impl EventBus {
         }
     }
 
+    /// Registers a control taking a separate value for each voice, e.g. a per-voice pitch offset
+    pub fn voice_control(&self, id: String, on_change: Box<Fn(usize, f32)>) {
+        let mut voice_controls = self.voice_controls.borrow_mut();
+        if let Some(_) = voice_controls.insert(id, on_change) {
+            panic!("Adding voice control twice");
+        }
+    }
+
+    /// Ids of the controls starting with `prefix`, sorted
+    pub fn controls(&self, prefix: &str) -> Vec<String> {
+        let mut ids: Vec<String> = self.controls.borrow().keys().filter(|id| id.starts_with(prefix)).cloned().collect();
+        ids.sort();
+        ids
+    }
+
+    /// Removes every control and voice control starting with `prefix`, so they can be added again
+    ///
+    /// The controls are dropped after letting go of the bus, as dropping them may remove more.
+    pub fn remove(&self, prefix: &str) {
+        let mut controls = self.controls.borrow_mut();
+        let ids: Vec<String> = controls.keys().filter(|id| id.starts_with(prefix)).cloned().collect();
+        let removed: Vec<Box<Fn(f32)>> = ids.iter().filter_map(|id| controls.remove(id)).collect();
+        drop(controls);
+        let mut voice_controls = self.voice_controls.borrow_mut();
+        let ids: Vec<String> = voice_controls.keys().filter(|id| id.starts_with(prefix)).cloned().collect();
+        let removed_voice: Vec<Box<Fn(usize, f32)>> = ids.iter().filter_map(|id| voice_controls.remove(id)).collect();
+        drop(voice_controls);
+        self.last_value.borrow_mut().retain(|id, _| !id.starts_with(prefix));
+        self.offsets.borrow_mut().retain(|id, _| !id.starts_with(prefix));
+        drop(removed);
+        drop(removed_voice);
+    }
+
+    /// Sets what gets told about triggers, only the outermost ones when controls trigger others
+    pub fn listen(&self, listener: Option<Box<Fn(&str, f32)>>) {
+        *self.listener.borrow_mut() = listener;
+    }
+
+    pub fn is_voice_control(&self, id: &str) -> bool {
+        self.voice_controls.borrow().contains_key(id)
+    }
+
+    pub fn trigger_voice(&self, id: String, voice: usize, value: f32) {
+        let voice_controls = self.voice_controls.borrow();
+        if let Some(f) = voice_controls.get(&id) {
+            f(voice, value);
+        }
+    }
+
+    /// Moves a control `offset` away from its last triggered value, which stays the same
+    pub fn offset(&self, id: String, offset: f32) {
+        let controls = self.controls.borrow();
+        if let Some(f) = controls.get(&id) {
+            self.offsets.borrow_mut().insert(id.clone(), offset);
+            f(self.value(id) + offset);
+        }
+    }
+
     pub fn modulate(&self, from: String, to: String) {
         let mut modulations = self.modulations.borrow_mut();
         let entries: &mut Vec<String> = modulations.entry(from).or_insert(vec![]);
@@ -46,21 +112,35 @@ impl EventBus {
         }
     }
 
+    /// Triggers a control without the listener being told, for values driven from within
+    /// rather than played, e.g. by an MSEG
+    pub fn drive(&self, id: String, value: f32) {
+        self.depth.set(self.depth.get() + 1);
+        self.trigger(id, value);
+        self.depth.set(self.depth.get() - 1);
+    }
+
     pub fn trigger(&self, id: String, value: f32) {
         let controls = self.controls.borrow();
-
-        console::log_1(&"trying to trigger".into());
-
         if let Some(f) = controls.get(&id) {
-            let mut last_value = self.last_value.borrow_mut();
-            last_value.insert(id.clone(), value);
-            f(value);
+            if self.depth.get() == 0 {
+                if let Some(listener) = self.listener.borrow().as_ref() {
+                    listener(&id, value);
+                }
+            }
+            self.last_value.borrow_mut().insert(id.clone(), value);
+            let offset = self.offsets.borrow().get(&id).cloned().unwrap_or(0.0);
+            self.depth.set(self.depth.get() + 1);
+            f(value + offset);
+            self.depth.set(self.depth.get() - 1);
         }
         let modulations = self.modulations.borrow();
         if let Some(modulations) = modulations.get(&id) {
+            self.depth.set(self.depth.get() + 1);
             for modulated_id in modulations {
                 self.trigger(modulated_id.to_string(), value);
             }
+            self.depth.set(self.depth.get() - 1);
         }
     }
 }
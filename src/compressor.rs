@@ -0,0 +1,91 @@
+use std::any::Any;
+
+use wasm_bindgen::prelude::*;
+use web_sys::{AudioContext, AudioNode, AudioParam, DynamicsCompressorNode, GainNode};
+
+use crate::audio::{AudioInput, AudioOutput};
+use crate::effect::Effect;
+use crate::get_bus;
+use crate::smooth;
+
+/// Converts decibels to an amplitude ratio
+pub fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+/// A compressor with makeup gain.
+///
+/// Its parameters are bus controls named after it: `threshold` (dBFS), `ratio`,
+/// `knee` (dB), `attack` and `release` (seconds) and `makeup` (dB), e.g.
+/// `comp.ratio` for a compressor named `comp`.
+pub struct Compressor {
+    name: String,
+    compressor: DynamicsCompressorNode,
+    makeup: GainNode,
+}
+
+impl Compressor {
+    pub fn new(name: String, ctx: AudioContext) -> Result<Compressor, JsValue> {
+        let bus = unsafe { get_bus() };
+        let compressor = ctx.create_dynamics_compressor()?;
+        let makeup = ctx.create_gain()?;
+        compressor.connect_with_audio_node(&makeup)?;
+
+        let params: [(&str, AudioParam, f32); 5] = [
+            ("threshold", compressor.threshold(), -24.0),
+            ("ratio", compressor.ratio(), 4.0),
+            ("knee", compressor.knee(), 6.0),
+            ("attack", compressor.attack(), 0.003),
+            ("release", compressor.release(), 0.25),
+        ];
+        for (id, param, initial) in params.iter().cloned() {
+            param.set_value_at_time(initial, ctx.current_time())?;
+            let c = ctx.clone();
+            bus.control(format!("{}.{}", name, id), initial, Box::new(move |v| {
+                smooth::set(&param, v, c.current_time(), smooth::DYNAMICS);
+            }));
+        }
+
+        let gain = makeup.gain();
+        let c = ctx.clone();
+        bus.control(format!("{}.makeup", name), 0.0, Box::new(move |v| {
+            smooth::set(&gain, db_to_gain(v), c.current_time(), smooth::GAIN);
+        }));
+
+        Ok(Compressor {
+            name,
+            compressor,
+            makeup,
+        })
+    }
+
+    /// How much the compressor is currently turning the signal down, in dB
+    pub fn reduction(&self) -> f32 {
+        self.compressor.reduction()
+    }
+}
+
+impl AudioInput for Compressor {
+    fn input(&self) -> AudioNode {
+        self.compressor.clone().into()
+    }
+}
+
+impl AudioOutput for Compressor {
+    fn output(&self) -> AudioNode {
+        self.makeup.clone().into()
+    }
+}
+
+impl Effect for Compressor {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl Drop for Compressor {
+    fn drop(&mut self) {
+        let bus = unsafe { get_bus() };
+        bus.remove(&format!("{}.", self.name));
+    }
+}
@@ -0,0 +1,260 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+use web_sys::{AudioContext, AudioNode, BiquadFilterNode, BiquadFilterType, ChannelMergerNode,
+              ChannelSplitterNode, DelayNode, GainNode};
+
+use crate::audio::{AudioInput, AudioOutput};
+use crate::effect::Effect;
+use crate::get_bus;
+use crate::smooth;
+
+/// Longest delay time, in seconds, offset included
+const MAX_TIME: f64 = 4.0;
+/// Highest feedback, keeping repeats from building up forever
+const MAX_FEEDBACK: f32 = 0.95;
+/// Q (in dB) of the damping filters, flat up to their cutoff so they never boost repeats
+const DAMPING_Q: f32 = -3.01;
+/// Insert mix it starts at, as it outputs the repeats only
+const DEFAULT_MIX: f32 = 0.3;
+
+/// Note values delays can sync to, with their length in beats
+const DIVISIONS: &[(&str, f32)] = &[
+    ("1/1", 4.0),
+    ("1/2", 2.0),
+    ("1/2.", 3.0),
+    ("1/4", 1.0),
+    ("1/4.", 1.5),
+    ("1/4t", 2.0 / 3.0),
+    ("1/8", 0.5),
+    ("1/8.", 0.75),
+    ("1/8t", 1.0 / 3.0),
+    ("1/16", 0.25),
+    ("1/16.", 0.375),
+    ("1/16t", 1.0 / 6.0),
+    ("1/32", 0.125),
+];
+
+/// Names of the note values a delay's `division` control picks from, starting at 1
+#[wasm_bindgen]
+pub fn delay_divisions() -> js_sys::Array {
+    let divisions = js_sys::Array::new();
+    for (name, _) in DIVISIONS {
+        divisions.push(&JsValue::from(*name));
+    }
+    divisions
+}
+
+struct Settings {
+    /// In ms
+    time: f32,
+    /// 0 for `time`, otherwise one plus an index in `DIVISIONS`
+    division: usize,
+    /// In BPM
+    tempo: f32,
+    feedback: f32,
+    /// Cutoff of the filter in the feedback loop, in Hz
+    damping: f32,
+    ping_pong: bool,
+    /// How much later the right side repeats than the left, in ms. Negative for earlier.
+    offset: f32,
+}
+
+/// A bus control of the delay: its name, initial value and how it changes the settings
+type Param = (&'static str, f32, fn(&mut Settings, f32));
+
+impl Settings {
+    /// Delay time in seconds, before the offset
+    fn seconds(&self) -> f64 {
+        match DIVISIONS.get(self.division.wrapping_sub(1)) {
+            Some((_, beats)) => (beats * 60.0 / self.tempo.max(1.0)) as f64,
+            None => self.time.max(0.0) as f64 / 1000.0,
+        }
+    }
+}
+
+/// Both sides of the delay. Routing in and around the loop goes through gains rather than
+/// connections, so switching ping-pong on and off ramps instead of clicking.
+struct Lines {
+    ctx: AudioContext,
+    settings: Settings,
+    left: DelayNode,
+    right: DelayNode,
+    dampers: [BiquadFilterNode; 2],
+    /// The right input into the right line, closed in ping-pong mode
+    right_in: GainNode,
+    /// The right input into the left line, which takes both in ping-pong mode
+    right_to_left: GainNode,
+    /// Feedback of each side into its own line, and across in ping-pong mode
+    left_left: GainNode,
+    right_right: GainNode,
+    left_right: GainNode,
+    right_left: GainNode,
+}
+
+impl Lines {
+    fn update(&self) {
+        let now = self.ctx.current_time();
+        let s = &self.settings;
+        let seconds = s.seconds();
+        let offset = s.offset as f64 / 1000.0;
+        let left = (seconds - offset.min(0.0)).max(0.0).min(MAX_TIME);
+        let right = (seconds + offset.max(0.0)).max(0.0).min(MAX_TIME);
+        smooth::set(&self.left.delay_time(), left as f32, now, smooth::TIME);
+        smooth::set(&self.right.delay_time(), right as f32, now, smooth::TIME);
+
+        for damper in &self.dampers {
+            smooth::set(&damper.frequency(), s.damping.max(20.0), now, smooth::FREQUENCY);
+        }
+
+        let feedback = s.feedback.max(0.0).min(MAX_FEEDBACK);
+        let (straight, across) = if s.ping_pong { (0.0, feedback) } else { (feedback, 0.0) };
+        smooth::set(&self.left_left.gain(), straight, now, smooth::GAIN);
+        smooth::set(&self.right_right.gain(), straight, now, smooth::GAIN);
+        smooth::set(&self.left_right.gain(), across, now, smooth::GAIN);
+        smooth::set(&self.right_left.gain(), across, now, smooth::GAIN);
+
+        // In ping-pong mode both sides go into the left line, so repeats start on the left
+        let (right_in, right_to_left) = if s.ping_pong { (0.0, 1.0) } else { (1.0, 0.0) };
+        smooth::set(&self.right_in.gain(), right_in, now, smooth::GAIN);
+        smooth::set(&self.right_to_left.gain(), right_to_left, now, smooth::GAIN);
+    }
+}
+
+/// A stereo delay with feedback, a low-pass damping the repeats, ping-pong mode and a
+/// stereo offset. It outputs the repeats only, leaving the dry signal to the insert slot
+/// or aux bus it's in.
+///
+/// Its parameters are bus controls named after it: `time` (ms), `division` (0 to use
+/// `time`, otherwise a note value from `delay_divisions`, starting at 1), `tempo` (BPM),
+/// `feedback` (0 to 0.95), `damping` (Hz), `ping_pong` (0 or 1) and `offset` (ms), e.g.
+/// `delay.feedback` for a delay named `delay`.
+pub struct Delay {
+    name: String,
+    input: ChannelSplitterNode,
+    output: ChannelMergerNode,
+    lines: Rc<RefCell<Lines>>,
+}
+
+impl Delay {
+    pub fn new(name: String, ctx: AudioContext) -> Result<Delay, JsValue> {
+        let bus = unsafe { get_bus() };
+        let input = ctx.create_channel_splitter_with_number_of_outputs(2)?;
+        let output = ctx.create_channel_merger_with_number_of_inputs(2)?;
+        let left = ctx.create_delay_with_max_delay_time(MAX_TIME)?;
+        let right = ctx.create_delay_with_max_delay_time(MAX_TIME)?;
+        let gain = || ctx.create_gain();
+        let (right_in, right_to_left) = (gain()?, gain()?);
+        let (left_left, right_right, left_right, right_left) = (gain()?, gain()?, gain()?, gain()?);
+
+        let mut dampers = Vec::with_capacity(2);
+        for (side, line) in [&left, &right].iter().enumerate() {
+            let damper = ctx.create_biquad_filter()?;
+            damper.set_type(BiquadFilterType::Lowpass);
+            damper.q().set_value_at_time(DAMPING_Q, ctx.current_time())?;
+            line.connect_with_audio_node(&damper)?;
+            damper.connect_with_audio_node_and_output_and_input(&output, 0, side as u32)?;
+            dampers.push(damper);
+        }
+        let dampers = [dampers.remove(0), dampers.remove(0)];
+
+        input.connect_with_audio_node_and_output(&left, 0)?;
+        input.connect_with_audio_node_and_output(&right_in, 1)?;
+        input.connect_with_audio_node_and_output(&right_to_left, 1)?;
+        right_in.connect_with_audio_node(&right)?;
+        right_to_left.connect_with_audio_node(&left)?;
+
+        dampers[0].connect_with_audio_node(&left_left)?;
+        dampers[0].connect_with_audio_node(&left_right)?;
+        dampers[1].connect_with_audio_node(&right_right)?;
+        dampers[1].connect_with_audio_node(&right_left)?;
+        left_left.connect_with_audio_node(&left)?;
+        left_right.connect_with_audio_node(&right)?;
+        right_right.connect_with_audio_node(&right)?;
+        right_left.connect_with_audio_node(&left)?;
+
+        let settings = Settings {
+            time: 375.0,
+            division: 0,
+            tempo: 120.0,
+            feedback: 0.4,
+            damping: 6000.0,
+            ping_pong: false,
+            offset: 0.0,
+        };
+        let initial: [Param; 7] = [
+            ("time", settings.time, |s, v| s.time = v),
+            ("division", settings.division as f32, |s, v| s.division = v.max(0.0) as usize),
+            ("tempo", settings.tempo, |s, v| s.tempo = v),
+            ("feedback", settings.feedback, |s, v| s.feedback = v),
+            ("damping", settings.damping, |s, v| s.damping = v),
+            ("ping_pong", settings.ping_pong as u8 as f32, |s, v| s.ping_pong = v >= 0.5),
+            ("offset", settings.offset, |s, v| s.offset = v),
+        ];
+
+        let lines = Rc::new(RefCell::new(Lines {
+            ctx,
+            settings,
+            left,
+            right,
+            dampers,
+            right_in,
+            right_to_left,
+            left_left,
+            right_right,
+            left_right,
+            right_left,
+        }));
+        lines.borrow().update();
+
+        for (id, value, apply) in initial.iter().cloned() {
+            let l = lines.clone();
+            bus.control(format!("{}.{}", name, id), value, Box::new(move |v| {
+                apply(&mut l.borrow_mut().settings, v);
+                l.borrow().update();
+            }));
+        }
+
+        Ok(Delay {
+            name,
+            input,
+            output,
+            lines,
+        })
+    }
+}
+
+impl AudioInput for Delay {
+    fn input(&self) -> AudioNode {
+        self.input.clone().into()
+    }
+}
+
+impl AudioOutput for Delay {
+    fn output(&self) -> AudioNode {
+        self.output.clone().into()
+    }
+}
+
+impl Effect for Delay {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn default_mix(&self) -> f32 {
+        DEFAULT_MIX
+    }
+}
+
+impl Drop for Delay {
+    fn drop(&mut self) {
+        let bus = unsafe { get_bus() };
+        bus.remove(&format!("{}.", self.name));
+        // The feedback loops would otherwise keep each other alive
+        let lines = self.lines.borrow();
+        lines.left.disconnect().unwrap_or(());
+        lines.right.disconnect().unwrap_or(());
+    }
+}
@@ -0,0 +1,107 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+use web_sys::{AudioContext, AudioNode};
+
+use crate::audio::{AudioInput, AudioOutput};
+use crate::compressor::Compressor;
+use crate::delay::Delay;
+use crate::reverb::Reverb;
+use crate::{get_bus, Filter};
+
+/// Names of the effect types `create_effect` can create
+const EFFECT_TYPES: &[&str] = &["filter", "compressor", "delay", "reverb"];
+
+/// A module processing audio, fitting into insert slots and aux buses
+pub trait Effect: AudioInput + AudioOutput {
+    /// To get back the concrete effect, e.g. for readouts
+    fn as_any(&self) -> &dyn Any;
+
+    /// Called regularly by the mixer, for work too heavy to do on every control change
+    fn tick(&self, _now: f64) -> Result<(), JsValue> {
+        Ok(())
+    }
+
+    /// Mix (0 to 1) of the insert slot it gets added to. Effects outputting only their wet
+    /// signal start lower, so adding them doesn't cut the dry signal.
+    fn default_mix(&self) -> f32 {
+        1.0
+    }
+}
+
+#[wasm_bindgen]
+pub fn effect_types() -> js_sys::Array {
+    let types = js_sys::Array::new();
+    for t in EFFECT_TYPES {
+        types.push(&JsValue::from(*t));
+    }
+    types
+}
+
+/// Creates an effect of type `kind` (see `effect_types`) with its controls named after `name`.
+///
+/// Names must be unique among the effects and instruments alive at the same time.
+pub fn create_effect(kind: &str, name: String, ctx: AudioContext) -> Result<Box<dyn Effect>, JsValue> {
+    match kind {
+        "filter" => Ok(Box::new(FilterEffect::new(name, ctx)?)),
+        "compressor" => Ok(Box::new(Compressor::new(name, ctx)?)),
+        "delay" => Ok(Box::new(Delay::new(name, ctx)?)),
+        "reverb" => Ok(Box::new(Reverb::new(name, ctx)?)),
+        _ => Err(format!("Unknown effect type {}", kind).into()),
+    }
+}
+
+/// A `Filter` with its frequency (in Hz) and resonance on the bus
+pub struct FilterEffect {
+    name: String,
+    filter: Rc<RefCell<Filter>>,
+}
+
+impl FilterEffect {
+    pub fn new(name: String, ctx: AudioContext) -> Result<FilterEffect, JsValue> {
+        let bus = unsafe { get_bus() };
+        let filter = Rc::new(RefCell::new(Filter::new(ctx)?));
+        let (frequency, resonance) = {
+            let f = filter.borrow();
+            (f.frequency, f.resonance)
+        };
+
+        let f = filter.clone();
+        bus.control(format!("{}.frequency", name), frequency as f32, Box::new(move |v| {
+            f.borrow_mut().set_frequency(v.max(0.0) as u32);
+        }));
+        let f = filter.clone();
+        bus.control(format!("{}.resonance", name), resonance, Box::new(move |v| {
+            f.borrow_mut().set_resonance(v);
+        }));
+
+        Ok(FilterEffect { name, filter })
+    }
+}
+
+impl AudioInput for FilterEffect {
+    fn input(&self) -> AudioNode {
+        self.filter.borrow().input()
+    }
+}
+
+impl AudioOutput for FilterEffect {
+    fn output(&self) -> AudioNode {
+        self.filter.borrow().output()
+    }
+}
+
+impl Effect for FilterEffect {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl Drop for FilterEffect {
+    fn drop(&mut self) {
+        let bus = unsafe { get_bus() };
+        bus.remove(&format!("{}.", self.name));
+    }
+}
@@ -0,0 +1,158 @@
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+use web_sys::AudioParam;
+
+use crate::TIME_PADDING;
+
+/// Number of linear segments approximating a curved stage
+const CURVE_STEPS: usize = 16;
+
+/// How a stage moves from its start value to its end value
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum CurveShape {
+    Linear = 0,
+    /// Rushes away from the start and settles into the end value, like an analog stage
+    Exponential = 1,
+    /// Eases out of the start and speeds up into the end value
+    Logarithmic = 2,
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EnvelopeStage {
+    Attack = 0,
+    Decay = 1,
+    Release = 2,
+}
+
+/// Progress (0 to 1) through a stage of `shape` at `t` (0 to 1) of its duration.
+///
+/// `tension` (0 to 1) sets how pronounced the curve is, 0 being linear.
+pub fn curve(shape: CurveShape, tension: f32, t: f32) -> f32 {
+    let k = tension * 10.0;
+    if k < 1e-3 {
+        return t;
+    }
+    match shape {
+        CurveShape::Linear => t,
+        CurveShape::Exponential => (1.0 - (-k * t).exp()) / (1.0 - (-k).exp()),
+        CurveShape::Logarithmic => 1.0 - curve(CurveShape::Exponential, tension, 1.0 - t),
+    }
+}
+
+/// Schedules `param` going from `from` to `to` over `duration` seconds following `shape`.
+///
+/// `param` must already be scheduled to be at `from` at `start`.
+pub fn ramp(param: &AudioParam, from: f32, to: f32, start: f64, duration: f64, shape: CurveShape, tension: f32) {
+    if shape == CurveShape::Linear {
+        param.linear_ramp_to_value_at_time(to, start + duration).unwrap();
+        return;
+    }
+    for i in 1..=CURVE_STEPS {
+        let t = i as f32 / CURVE_STEPS as f32;
+        let value = from + (to - from) * curve(shape, tension, t);
+        param.linear_ramp_to_value_at_time(value, start + duration * t as f64).unwrap();
+    }
+}
+
+/// A DAHDSR envelope. Times are in milliseconds.
+#[wasm_bindgen]
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Envelope {
+    pub delay: f32,
+    pub attack: f32,
+    pub hold: f32,
+    pub decay: f32,
+    pub sustain: f32,
+    pub release: f32,
+    pub attack_shape: CurveShape,
+    pub attack_tension: f32,
+    pub decay_shape: CurveShape,
+    pub decay_tension: f32,
+    pub release_shape: CurveShape,
+    pub release_tension: f32,
+}
+
+impl Default for Envelope {
+    fn default() -> Envelope {
+        Envelope {
+            delay: 0.0,
+            attack: 30.0,
+            hold: 0.0,
+            decay: 300.0,
+            sustain: 1.0, // out of 1
+            release: 800.0,
+            attack_shape: CurveShape::Linear,
+            attack_tension: 0.5,
+            decay_shape: CurveShape::Exponential,
+            decay_tension: 0.5,
+            release_shape: CurveShape::Exponential,
+            release_tension: 0.5,
+        }
+    }
+}
+
+#[wasm_bindgen]
+pub fn default_envelope() -> Envelope {
+    Default::default()
+}
+
+impl Envelope {
+    pub fn adsr(&self) -> (f32, f32, f32, f32) {
+        (self.attack, self.decay, self.sustain, self.release)
+    }
+
+    pub fn set_curve(&mut self, stage: EnvelopeStage, shape: CurveShape, tension: f32) {
+        match stage {
+            EnvelopeStage::Attack => {
+                self.attack_shape = shape;
+                self.attack_tension = tension;
+            },
+            EnvelopeStage::Decay => {
+                self.decay_shape = shape;
+                self.decay_tension = tension;
+            },
+            EnvelopeStage::Release => {
+                self.release_shape = shape;
+                self.release_tension = tension;
+            },
+        }
+    }
+
+    /// Schedules the delay, attack, hold and decay stages on `param`, which must be at `from` at `start`.
+    ///
+    /// The attack goes up to `peak` and the decay settles on `sustain`.
+    pub fn start(&self, param: &AudioParam, start: f64, from: f32, peak: f32, sustain: f32) {
+        // Delay
+        let attack_start = start + self.delay as f64 / 1000.0;
+        param.set_value_at_time(from, attack_start).unwrap();
+
+        // Attack
+        let attack_time = TIME_PADDING + self.attack as f64 / 1000.0;
+        ramp(param, from, peak, attack_start, attack_time, self.attack_shape, self.attack_tension);
+
+        // Hold
+        let decay_start = attack_start + attack_time + self.hold as f64 / 1000.0;
+        param.set_value_at_time(peak, decay_start).unwrap();
+
+        // Decay
+        let decay_time = TIME_PADDING + self.decay as f64 / 1000.0;
+        ramp(param, peak, sustain, decay_start, decay_time, self.decay_shape, self.decay_tension);
+    }
+
+    /// How long (in seconds) the release stage takes
+    pub fn release_time(&self) -> f64 {
+        TIME_PADDING + self.release as f64 / 1000.0
+    }
+
+    /// Schedules the release stage on `param` from wherever it is at `now` down to `rest`.
+    pub fn end(&self, param: &AudioParam, now: f64, rest: f32) {
+        let from = param.value();
+        param.cancel_scheduled_values(now).unwrap();
+        param.set_value_at_time(from, now).unwrap();
+        let release_time = self.release_time();
+        ramp(param, from, rest, now, release_time, self.release_shape, self.release_tension);
+    }
+}
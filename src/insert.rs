@@ -0,0 +1,242 @@
+use wasm_bindgen::prelude::*;
+use web_sys::{AudioContext, AudioNode, GainNode};
+
+use crate::audio::{AudioInput, AudioOutput};
+use crate::effect::Effect;
+use crate::get_bus;
+use crate::smooth;
+
+/// How long (in seconds) a slot fades out before being rewired, by then fully dry
+const FADE_OUT: f64 = smooth::GAIN.time * 2.0;
+
+/// An effect in an insert chain, mixed with its dry input.
+///
+/// Its mix (0 to 1, starting at the effect's default) and bypass (on above 0.5) are bus
+/// controls named after it, e.g. `delay.mix` for an effect named `delay`.
+pub struct InsertSlot {
+    pub name: String,
+    ctx: AudioContext,
+    effect: Box<dyn Effect>,
+    input: GainNode,
+    dry: GainNode,
+    wet: GainNode,
+    output: GainNode,
+    mix: f32,
+    bypassed: bool,
+    /// Faded fully dry ahead of being moved or removed
+    fading: bool,
+}
+
+impl InsertSlot {
+    pub fn new(ctx: AudioContext, name: String, effect: Box<dyn Effect>) -> Result<InsertSlot, JsValue> {
+        let now = ctx.current_time();
+        let mix = effect.default_mix();
+        let input = ctx.create_gain()?;
+        let dry = ctx.create_gain()?;
+        let wet = ctx.create_gain()?;
+        let output = ctx.create_gain()?;
+        // Starts fully dry, fading in once wired
+        dry.gain().set_value_at_time(1.0, now)?;
+        wet.gain().set_value_at_time(0.0, now)?;
+
+        input.connect_with_audio_node(&dry)?;
+        input.connect_with_audio_node(&effect.input())?;
+        effect.output().connect_with_audio_node(&wet)?;
+        dry.connect_with_audio_node(&output)?;
+        wet.connect_with_audio_node(&output)?;
+
+        Ok(InsertSlot {
+            name,
+            ctx,
+            effect,
+            input,
+            dry,
+            wet,
+            output,
+            mix,
+            bypassed: false,
+            fading: false,
+        })
+    }
+
+    pub fn effect(&self) -> &dyn Effect {
+        &*self.effect
+    }
+
+    pub fn set_mix(&mut self, mix: f32) {
+        self.mix = mix.max(0.0).min(1.0);
+        self.update();
+    }
+
+    pub fn set_bypass(&mut self, bypassed: bool) {
+        self.bypassed = bypassed;
+        self.update();
+    }
+
+    fn set_fading(&mut self, fading: bool) {
+        self.fading = fading;
+        self.update();
+    }
+
+    /// Fades the wet and dry levels to where they should be
+    fn update(&self) {
+        let mix = if self.bypassed || self.fading { 0.0 } else { self.mix };
+        let now = self.ctx.current_time();
+        smooth::set(&self.dry.gain(), 1.0 - mix, now, smooth::GAIN);
+        smooth::set(&self.wet.gain(), mix, now, smooth::GAIN);
+    }
+}
+
+impl AudioInput for InsertSlot {
+    fn input(&self) -> AudioNode {
+        self.input.clone().into()
+    }
+}
+
+impl AudioOutput for InsertSlot {
+    fn output(&self) -> AudioNode {
+        self.output.clone().into()
+    }
+}
+
+impl Drop for InsertSlot {
+    fn drop(&mut self) {
+        self.input.disconnect().unwrap();
+        self.effect.output().disconnect().unwrap();
+        self.output.disconnect().unwrap();
+        let bus = unsafe { get_bus() };
+        bus.remove(&format!("{}.", self.name));
+    }
+}
+
+/// A change to the chain waiting for its slot to fade out
+enum Pending {
+    Remove(String),
+    Move(String, usize),
+}
+
+/// An ordered chain of insert slots.
+///
+/// Slots are only rewired while fully dry, so adding, moving and removing them
+/// doesn't click. Moves and removals fade the slot out first and happen on a
+/// later `update`.
+pub struct InsertChain {
+    ctx: AudioContext,
+    head: GainNode,
+    tail: GainNode,
+    pub slots: Vec<InsertSlot>,
+    pending: Vec<(f64, Pending)>,
+}
+
+impl InsertChain {
+    pub fn new(ctx: AudioContext) -> Result<InsertChain, JsValue> {
+        let head = ctx.create_gain()?;
+        let tail = ctx.create_gain()?;
+        head.connect_with_audio_node(&tail)?;
+        Ok(InsertChain {
+            ctx,
+            head,
+            tail,
+            slots: vec![],
+            pending: vec![],
+        })
+    }
+
+    /// Appends `slot` to the chain, returning its position
+    pub fn add(&mut self, slot: InsertSlot) -> usize {
+        self.slots.push(slot);
+        self.rewire();
+        self.slots.last().unwrap().update();
+        self.slots.len() - 1
+    }
+
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.slots.iter().position(|s| s.name == name)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut InsertSlot> {
+        self.slots.iter_mut().find(|s| s.name == name)
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.fade_out(Pending::Remove(name.to_string()));
+    }
+
+    /// Moves the slot named `name` to position `to`
+    pub fn move_to(&mut self, name: &str, to: usize) {
+        self.fade_out(Pending::Move(name.to_string(), to));
+    }
+
+    fn fade_out(&mut self, change: Pending) {
+        let name = match &change {
+            Pending::Remove(name) | Pending::Move(name, _) => name.clone(),
+        };
+        if let Some(slot) = self.get_mut(&name) {
+            slot.set_fading(true);
+        }
+        self.pending.push((self.ctx.current_time() + FADE_OUT, change));
+    }
+
+    /// Ticks the effects and applies the changes whose slots have faded out
+    pub fn update(&mut self, now: f64) -> Result<(), JsValue> {
+        for slot in &self.slots {
+            slot.effect().tick(now)?;
+        }
+        if self.pending.iter().all(|(due, _)| *due > now) {
+            return Ok(());
+        }
+        let (due, waiting): (Vec<_>, Vec<_>) = self.pending.drain(..).partition(|(due, _)| *due <= now);
+        self.pending = waiting;
+        let mut moved = vec![];
+        for (_, change) in due {
+            match change {
+                Pending::Remove(name) => {
+                    if let Some(idx) = self.position(&name) {
+                        self.slots.remove(idx);
+                    }
+                }
+                Pending::Move(name, to) => {
+                    if let Some(idx) = self.position(&name) {
+                        let slot = self.slots.remove(idx);
+                        let to = to.min(self.slots.len());
+                        self.slots.insert(to, slot);
+                        moved.push(name);
+                    }
+                }
+            }
+        }
+        self.rewire();
+        for name in moved {
+            if let Some(slot) = self.get_mut(&name) {
+                slot.set_fading(false);
+            }
+        }
+        Ok(())
+    }
+
+    /// Connects the head through every slot in order to the tail
+    fn rewire(&self) {
+        self.head.disconnect().unwrap();
+        for slot in &self.slots {
+            slot.output.disconnect().unwrap();
+        }
+        let mut from: AudioNode = self.head.clone().into();
+        for slot in &self.slots {
+            from.connect_with_audio_node(&slot.input()).unwrap();
+            from = slot.output();
+        }
+        from.connect_with_audio_node(&self.tail).unwrap();
+    }
+}
+
+impl AudioInput for InsertChain {
+    fn input(&self) -> AudioNode {
+        self.head.clone().into()
+    }
+}
+
+impl AudioOutput for InsertChain {
+    fn output(&self) -> AudioNode {
+        self.tail.clone().into()
+    }
+}
@@ -0,0 +1,67 @@
+use wasm_bindgen::prelude::*;
+use web_sys::AudioContext;
+
+use crate::audio::{self, AudioOutput};
+use crate::{get_bus, Mixer, Subjam};
+
+/// Names of the instrument types `create_instrument` can create
+const INSTRUMENT_TYPES: &[&str] = &["subjam"];
+
+/// A module playing notes into the `Mixer`.
+///
+/// Its parameters are bus controls named after it, e.g. `subjam.osc_mix` for an
+/// instrument named `subjam`.
+pub trait Instrument: AudioOutput {
+    fn name(&self) -> &str;
+    fn note_on(&mut self, note: u8, velocity: u8);
+    fn note_off(&mut self, note: u8);
+    /// Updates whatever runs at control rate, like modulation. Called regularly, e.g. every frame.
+    fn tick(&mut self) {}
+    fn save_patch(&self) -> Result<JsValue, JsValue>;
+    fn load_patch(&mut self, patch: &JsValue) -> Result<(), JsValue>;
+
+    /// Ids of the bus controls making up its parameters
+    fn parameters(&self) -> Vec<String> {
+        let bus = unsafe { get_bus() };
+        bus.controls(&format!("{}.", self.name()))
+    }
+
+    /// Plays into channel `at` of `mixer`
+    fn connect_to_mixer(&self, mixer: &Mixer, at: usize) -> Result<(), JsValue> where Self: Sized {
+        if at >= mixer.channel_count() {
+            return Err(format!("No mixer channel {}, there are {}", at, mixer.channel_count()).into());
+        }
+        audio::connect_to_one(self, mixer, at);
+        Ok(())
+    }
+}
+
+#[wasm_bindgen]
+pub fn instrument_types() -> js_sys::Array {
+    let types = js_sys::Array::new();
+    for t in INSTRUMENT_TYPES {
+        types.push(&JsValue::from(*t));
+    }
+    types
+}
+
+/// Fails if an instrument or effect alive already has controls named after `name`
+pub fn check_name(name: &str) -> Result<(), JsValue> {
+    let bus = unsafe { get_bus() };
+    if !bus.controls(&format!("{}.", name)).is_empty() {
+        return Err(format!("Name {} already taken", name).into());
+    }
+    Ok(())
+}
+
+/// Creates an instrument of type `kind` (see `instrument_types`) with its controls named after `name`.
+///
+/// Names must be unique among the instruments and effects alive at the same time.
+#[wasm_bindgen]
+pub fn create_instrument(kind: &str, name: String, ctx: AudioContext) -> Result<JsValue, JsValue> {
+    check_name(&name)?;
+    match kind {
+        "subjam" => Ok(Subjam::with_name(name, ctx)?.into()),
+        _ => Err(format!("Unknown instrument type {}", kind).into()),
+    }
+}
@@ -2,13 +2,46 @@ use wasm_bindgen::prelude::*;
 use web_sys::console;
 use web_sys::window;
 use js_sys;
-use web_sys::{AudioContext, AudioNode, BiquadFilterType, OscillatorType, OscillatorNode, GainNode, BiquadFilterNode, AudioParam};
+use web_sys::{AudioBuffer, AudioContext, AudioNode, BiquadFilterType, OscillatorType, OscillatorNode, GainNode, BiquadFilterNode, AudioParam, ConstantSourceNode, ChannelCountMode, ChannelInterpretation};
 mod audio;
 mod cv;
 mod bus;
+mod worklet;
+mod noise;
+mod notes;
+mod patch;
+mod envelope;
+mod mseg;
+mod velocity;
+mod modulation;
+mod limiter;
+mod effect;
+mod insert;
+mod meter;
+mod compressor;
+mod delay;
+mod reverb;
+mod smooth;
+mod automation;
+pub mod instrument;
 use audio::{AudioInput, AudioOutput, AudioInputs};
 
 use bus::EventBus;
+use worklet::SyncOscillator;
+use noise::{Noise, NoiseColor};
+use notes::{GlideMode, NotePriority, NoteStack, VoiceMode};
+use patch::{MsegPatch, Patch, SplitPatch};
+use mseg::MsegPlayer;
+use envelope::{CurveShape, Envelope, EnvelopeStage};
+use velocity::{Velocity, VelocityCurve};
+use modulation::{Lfo, LfoShape, ModMatrix, ModSlot, VoiceSources};
+use limiter::Limiter;
+use effect::create_effect;
+use insert::{InsertChain, InsertSlot};
+use meter::{Meter, Meters};
+use compressor::Compressor;
+use reverb::{Reverb, ReverbPatch};
+use instrument::Instrument;
 
 /// Converts a midi note to frequency
 ///
@@ -17,34 +50,20 @@ pub fn midi_to_freq(note: u8) -> f32 {
     27.5 * 2f32.powf((note as f32 - 21.0) / 12.0)
 }
 
-#[wasm_bindgen]
-#[derive(Clone)]
-pub struct Envelope {
-    pub attack: u32,
-    pub decay: u32,
-    pub sustain: f32,
-    pub release: u32,
-}
-
-impl Default for Envelope {
-    fn default() -> Envelope {
-        Envelope {
-            attack: 30, // in milliseconds
-            decay: 300, // in milliseconds
-            sustain: 1.0, // out of 1
-            release: 800, // in milliseconds
-        }
-    }
-}
-
-#[wasm_bindgen]
-pub fn default_envelope() -> Envelope {
-    Default::default()
-}
+/// Q used by filter types whose Q isn't driven by the resonance control
+const DEFAULT_Q: f32 = std::f32::consts::FRAC_1_SQRT_2;
 
-impl Envelope {
-    pub fn adsr(&self) -> (u32, u32, f32, u32) {
-        (self.attack, self.decay, self.sustain, self.release)
+/// Maps the resonance control onto a biquad's (Q, gain) for the given filter type.
+///
+/// Lowpass and highpass read Q as the resonant peak in dB. Bandpass, notch and
+/// allpass read it as a bandwidth factor, which gets useless near 0, so resonance
+/// is mapped exponentially from `DEFAULT_Q` up. Peaking and shelving filters boost
+/// by `resonance` dB instead.
+pub fn filter_q(filter_type: BiquadFilterType, resonance: f32) -> (f32, f32) {
+    match filter_type {
+        BiquadFilterType::Lowpass | BiquadFilterType::Highpass => (resonance, 0.0),
+        BiquadFilterType::Peaking | BiquadFilterType::Lowshelf | BiquadFilterType::Highshelf => (DEFAULT_Q, resonance),
+        _ => (DEFAULT_Q * 2f32.powf(resonance / 5.0), 0.0),
     }
 }
 
@@ -79,12 +98,12 @@ impl Filter {
 
     pub fn set_frequency(&mut self, freq: u32) {
         self.frequency = freq;
-        self.filter.frequency().set_value_at_time(freq as f32, self.ctx.current_time()).unwrap();
+        smooth::set(&self.filter.frequency(), freq as f32, self.ctx.current_time(), smooth::FREQUENCY);
     }
 
     pub fn set_resonance(&mut self, q: f32) {
         self.resonance = q;
-        self.filter.q().set_value_at_time(q, self.ctx.current_time()).unwrap();
+        smooth::set(&self.filter.q(), q, self.ctx.current_time(), smooth::Q);
     }
 }
 
@@ -108,27 +127,128 @@ impl AudioOutput for Oscillator {
 
 impl AudioInput for Channel {
     fn input(&self) -> AudioNode {
-        self.gain.clone().into()
+        self.input.clone().into()
     }
 }
 
 impl AudioOutput for Channel {
     fn output(&self) -> AudioNode {
-        self.gain.clone().into()
+        self.gate.clone().into()
     }
 }
 
 use std::collections::HashMap;
 
+/// An oscillator playing one or two octaves below its voice
+pub struct SubOscillator {
+    pub osc: OscillatorNode,
+    pub gain: GainNode,
+    /// Follows the voice's amp envelope, peaking at the velocity's level alone
+    pub vca: GainNode,
+    pub octave: u8,
+}
+
 pub struct Voice {
     pub unison: usize,
     pub oscs: Vec<OscillatorNode>,
+    pub sync: Vec<SyncOscillator>,
+    pub synced: bool,
+    pub sub: Option<SubOscillator>,
+    /// Cutoff offset in cents for the note being played
+    pub filter_offset: f32,
     pub gain: GainNode,
-    pub filter: BiquadFilterNode
+    pub filter: BiquadFilterNode,
+    /// Modulation inputs, in cents for pitch and cutoff, added to the params they feed
+    pub pitch_mod: ConstantSourceNode,
+    pub cutoff_mod: ConstantSourceNode,
+    pub resonance_mod: ConstantSourceNode,
+    /// Per-voice modulation sources for the note being played
+    pub note: u8,
+    pub velocity: f32,
+    pub random: f32,
+    /// Smoothed towards `aftertouch_target` on every tick
+    pub aftertouch: f32,
+    pub aftertouch_target: f32,
+    /// Gain the amp envelope peaks at
+    pub peak: f32,
+    /// Context time at which the amp envelope is done releasing, infinite while held
+    pub silent_at: f64,
 }
 
 const TIME_PADDING: f64 = 0.003;
 const FILTER_MAX_FREQ: u32 = 7200;
+/// Default filter envelope amount, in cents
+const FILTER_ENV_AMOUNT: f32 = 2400.0;
+
+/// Default center note for filter key tracking
+const KEY_CENTER: u8 = 60;
+
+/// Time constant (in seconds) smoothing aftertouch, which comes in coarse steps
+const AFTERTOUCH_SMOOTHING: f64 = 0.05;
+
+/// Subjam's output gain with a single voice sounding
+const OUT_GAIN: f32 = 0.5;
+
+/// Registers a voice control smoothly setting `params`, one per voice
+fn voice_mod_control(ctx: &AudioContext, id: String, params: Vec<AudioParam>) {
+    let bus = unsafe { get_bus() };
+    let ctx = ctx.clone();
+    bus.voice_control(id, Box::new(move |voice, v| {
+        if let Some(param) = params.get(voice) {
+            smooth::set(param, v, ctx.current_time(), smooth::MODULATION);
+        }
+    }));
+}
+
+/// A constant source, initially 0, for modulating the params it gets connected to
+fn mod_input(ctx: &AudioContext) -> Result<ConstantSourceNode, JsValue> {
+    let source = ctx.create_constant_source()?;
+    source.offset().set_value_at_time(0.0, ctx.current_time())?;
+    Ok(source)
+}
+
+/// Detune (in cents) of the filter when its envelope is at `level` (0 to 1).
+///
+/// The envelope sweeps `amount` cents around the voice's cutoff (the global cutoff
+/// moved by `offset` cents), so negative amounts mirror positive ones and the filter
+/// always settles back on the voice's cutoff after release.
+pub fn filter_envelope_detune(offset: f32, amount: f32, level: f32) -> f32 {
+    offset + amount * level
+}
+
+/// Detune (in cents) the filter envelope's stages head for
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FilterEnvelopeTargets {
+    /// Reached by the attack, and held
+    pub peak: f32,
+    /// Settled on by the decay
+    pub sustain: f32,
+    /// Settled on by the release
+    pub rest: f32,
+}
+
+/// Stage targets of a filter envelope sweeping `amount` cents around a voice's cutoff
+/// moved by `offset` cents, with `sustain` from 0 to 1
+pub fn filter_envelope_targets(offset: f32, amount: f32, sustain: f32) -> FilterEnvelopeTargets {
+    FilterEnvelopeTargets {
+        peak: filter_envelope_detune(offset, amount, 1.0),
+        sustain: filter_envelope_detune(offset, amount, sustain),
+        rest: filter_envelope_detune(offset, amount, 0.0),
+    }
+}
+
+/// Where (0 to 1) the filter envelope is at given the filter's `detune`, inverting
+/// `filter_envelope_detune`. Always 0 when `amount` is 0.
+pub fn filter_envelope_level(offset: f32, amount: f32, detune: f32) -> f32 {
+    if amount != 0.0 { (detune - offset) / amount } else { 0.0 }
+}
+
+/// Cutoff offset (in cents) for `note`, following the keyboard by `amount` (0 to 1) around `center`.
+///
+/// At 100% the cutoff moves a semitone per semitone played.
+pub fn key_tracking(note: u8, center: u8, amount: f32) -> f32 {
+    (note as f32 - center as f32) * 100.0 * amount
+}
 
 impl Voice {
     pub fn new(ctx: &AudioContext, unison: usize) -> Result<Voice, JsValue> {
@@ -137,116 +257,293 @@ impl Voice {
         let mut oscs: Vec<OscillatorNode> = vec![];
         g.gain().set_value_at_time(0.0, ctx.current_time())?;
         f.connect_with_audio_node(&g)?;
+        let pitch_mod = mod_input(ctx)?;
         for i in 0..unison {
             let o = ctx.create_oscillator()?;
             o.detune().set_value(i as f32 * 0.5);
             o.connect_with_audio_node(&f)?;
+            pitch_mod.connect_with_audio_param(&o.detune())?;
             oscs.push(o);
         }
-        Ok(Voice { unison: unison, oscs: oscs, gain: g, filter: f})
+        Voice::with_nodes(ctx, unison, oscs, pitch_mod, g, f)
+    }
+
+    /// Creates a voice without oscillators, filtering and enveloping `source` instead.
+    pub fn with_source(ctx: &AudioContext, source: &AudioNode) -> Result<Voice, JsValue> {
+        let f = ctx.create_biquad_filter()?;
+        let g = ctx.create_gain()?;
+        g.gain().set_value_at_time(0.0, ctx.current_time())?;
+        f.connect_with_audio_node(&g)?;
+        source.connect_with_audio_node(&f)?;
+        Voice::with_nodes(ctx, 1, vec![], mod_input(ctx)?, g, f)
+    }
+
+    fn with_nodes(ctx: &AudioContext, unison: usize, oscs: Vec<OscillatorNode>, pitch_mod: ConstantSourceNode, gain: GainNode, filter: BiquadFilterNode) -> Result<Voice, JsValue> {
+        let cutoff_mod = mod_input(ctx)?;
+        let resonance_mod = mod_input(ctx)?;
+        cutoff_mod.connect_with_audio_param(&filter.detune())?;
+        resonance_mod.connect_with_audio_param(&filter.q())?;
+        Ok(Voice {
+            unison,
+            oscs,
+            sync: vec![],
+            synced: false,
+            sub: None,
+            filter_offset: 0.0,
+            gain,
+            filter,
+            pitch_mod,
+            cutoff_mod,
+            resonance_mod,
+            note: KEY_CENTER,
+            velocity: 0.0,
+            random: 0.0,
+            aftertouch: 0.0,
+            aftertouch_target: 0.0,
+            peak: 0.0,
+            silent_at: 0.0,
+        })
     }
 
     pub fn start(&self) {
         for o in &self.oscs {
             o.start().unwrap();
         }
+        if let Some(sub) = &self.sub {
+            sub.osc.start().unwrap();
+        }
+        for m in &[&self.pitch_mod, &self.cutoff_mod, &self.resonance_mod] {
+            m.start().unwrap();
+        }
     }
 
     pub fn stop(&self) {
         for o in &self.oscs {
             o.stop().unwrap();
         }
+        for s in &self.sync {
+            s.stop().unwrap();
+        }
+        if let Some(sub) = &self.sub {
+            sub.osc.stop().unwrap();
+        }
+        for m in &[&self.pitch_mod, &self.cutoff_mod, &self.resonance_mod] {
+            m.stop().unwrap();
+        }
+    }
+
+    /// Adds a sub oscillator playing into `to`. Must be called before `start`.
+    ///
+    /// The sub skips the voice's filter and gain, going through a VCA of its own following
+    /// the amp envelope, so its level doesn't depend on the voice's.
+    pub fn add_sub(&mut self, ctx: &AudioContext, waveform: OscillatorType, octave: u8, level: f32, to: &AudioNode) -> Result<(), JsValue> {
+        let osc = ctx.create_oscillator()?;
+        let gain = ctx.create_gain()?;
+        let vca = ctx.create_gain()?;
+        osc.set_type(waveform);
+        gain.gain().set_value_at_time(level, ctx.current_time())?;
+        vca.gain().set_value_at_time(0.0, ctx.current_time())?;
+        osc.connect_with_audio_node(&gain)?;
+        gain.connect_with_audio_node(&vca)?;
+        vca.connect_with_audio_node(to)?;
+        self.pitch_mod.connect_with_audio_param(&osc.detune())?;
+        self.sub = Some(SubOscillator { osc, gain, vca, octave });
+        Ok(())
+    }
+
+    pub fn set_sub_waveform(&self, waveform: OscillatorType) {
+        if let Some(sub) = &self.sub {
+            sub.osc.set_type(waveform);
+        }
+    }
+
+    pub fn set_sub_octave(&mut self, ctx: &AudioContext, octave: u8) {
+        if let Some(sub) = &mut self.sub {
+            let freq = sub.osc.frequency().value() * 2f32.powi(sub.octave as i32 - octave as i32);
+            sub.octave = octave;
+            smooth::set(&sub.osc.frequency(), freq, ctx.current_time(), smooth::FREQUENCY);
+        }
+    }
+
+    pub fn set_sub_level(&self, ctx: &AudioContext, level: f32) {
+        if let Some(sub) = &self.sub {
+            smooth::set(&sub.gain.gain(), level, ctx.current_time(), smooth::GAIN);
+        }
     }
 
     pub fn set_waveform(&mut self, waveform: OscillatorType) {
         for o in &self.oscs {
             o.set_type(waveform);
         }
+        for s in &self.sync {
+            s.set_type(waveform);
+        }
+    }
+
+    /// Creates the hard-synced oscillators if they don't exist yet, which requires the
+    /// worklets to be loaded.
+    pub fn prepare_sync(&mut self, ctx: &AudioContext, waveform: OscillatorType, ratio: f32) -> Result<(), JsValue> {
+        if !self.sync.is_empty() {
+            return Ok(());
+        }
+        for (i, o) in self.oscs.iter().enumerate() {
+            let s = SyncOscillator::new(ctx)?;
+            s.set_type(waveform);
+            s.detune().set_value(i as f32 * 0.5);
+            s.ratio().set_value(ratio);
+            s.frequency().set_value(o.frequency().value());
+            self.pitch_mod.connect_with_audio_param(&s.detune())?;
+            self.sync.push(s);
+        }
+        Ok(())
+    }
+
+    /// Swaps the voice's oscillators for hard-synced ones (or back).
+    ///
+    /// The synced oscillators are created the first time sync is enabled (see `prepare_sync`).
+    pub fn set_sync(&mut self, ctx: &AudioContext, enabled: bool, waveform: OscillatorType, ratio: f32) -> Result<(), JsValue> {
+        if enabled == self.synced {
+            return Ok(());
+        }
+        self.prepare_sync(ctx, waveform, ratio)?;
+        for (o, s) in self.oscs.iter().zip(&self.sync) {
+            if enabled {
+                o.disconnect_with_audio_node(&self.filter)?;
+                s.node().connect_with_audio_node(&self.filter)?;
+            } else {
+                s.node().disconnect_with_audio_node(&self.filter)?;
+                o.connect_with_audio_node(&self.filter)?;
+            }
+        }
+        self.synced = enabled;
+        Ok(())
+    }
+
+    pub fn set_sync_ratio(&self, ctx: &AudioContext, ratio: f32) {
+        let now = ctx.current_time();
+        for s in &self.sync {
+            smooth::set(&s.ratio(), ratio, now, smooth::RATIO);
+        }
+    }
+
+    /// Jumps straight to `ratio`, for notes starting
+    pub fn start_sync_ratio(&self, ctx: &AudioContext, ratio: f32) {
+        let now = ctx.current_time();
+        for s in &self.sync {
+            let param = s.ratio();
+            param.cancel_scheduled_values(now).unwrap();
+            param.set_value_at_time(ratio, now).unwrap();
+        }
     }
 
     pub fn set_filter_frequency(&self, ctx: &AudioContext, freq: u32) {
         let now = ctx.current_time();
-        self.filter.frequency().set_value_at_time(freq as f32, now).unwrap();
+        smooth::set(&self.filter.frequency(), freq as f32, now, smooth::FREQUENCY);
+    }
+
+    pub fn set_filter_type(&self, filter_type: BiquadFilterType) {
+        self.filter.set_type(filter_type);
     }
 
-    pub fn set_filter_resonance(&self, ctx: &AudioContext, q: f32) {
+    /// Moves this voice's cutoff by `cents`, taking effect on the next envelope stage
+    pub fn set_filter_offset(&mut self, cents: f32) {
+        self.filter_offset = cents;
+    }
+
+    pub fn set_filter_q(&self, ctx: &AudioContext, q: f32, gain: f32) {
         let now = ctx.current_time();
-        self.filter.q().set_value_at_time(q, now).unwrap();
+        smooth::set(&self.filter.q(), q, now, smooth::Q);
+        smooth::set(&self.filter.gain(), gain, now, smooth::Q);
+    }
+
+    /// Every frequency param in the voice, along with its ratio to the voice's pitch
+    fn frequencies(&self) -> Vec<(AudioParam, f32)> {
+        let mut params: Vec<(AudioParam, f32)> = self.oscs.iter().map(|o| (o.frequency(), 1.0)).collect();
+        params.extend(self.sync.iter().map(|s| (s.frequency(), 1.0)));
+        if let Some(sub) = &self.sub {
+            params.push((sub.osc.frequency(), 1.0 / 2f32.powi(sub.octave as i32)));
+        }
+        params
     }
 
     pub fn set_freq(&mut self, ctx: &AudioContext, freq: f32) {
         let now = ctx.current_time();
-        for o in &self.oscs {
-            o.frequency().set_value_at_time(freq, now + TIME_PADDING).unwrap();
+        for (param, ratio) in self.frequencies() {
+            param.cancel_scheduled_values(now).unwrap();
+            param.set_value_at_time(freq * ratio, now + TIME_PADDING).unwrap();
         }
     }
 
-    pub fn amp_envelope_start(&self, ctx: &AudioContext, env: &Envelope, mut max_gain: f32, velocity: u8) {
+    /// Glides exponentially from the current pitch to `freq` over `time` seconds
+    pub fn glide_freq(&mut self, ctx: &AudioContext, freq: f32, time: f64) {
         let now = ctx.current_time();
-        let vel = velocity as f32 / 127.0;
-        max_gain = (vel * max_gain) / self.unison as f32;
-        let attack_s = env.attack as f64 / 1000.0;
-        let decay_s = env.decay as f64 / 1000.0;
-        let gain: AudioParam = self.gain.gain();
+        for (param, ratio) in self.frequencies() {
+            param.cancel_scheduled_values(now).unwrap();
+            param.set_value_at_time(param.value(), now).unwrap();
+            param.exponential_ramp_to_value_at_time(freq * ratio, now + time).unwrap();
+        }
+    }
 
-        // Init envelope (Set value to current value and quickly ramp to 0 to avoid clicks)
-        gain.cancel_scheduled_values(now).unwrap();
-        gain.set_value_at_time(max_gain, now).unwrap();
-        gain.linear_ramp_to_value_at_time(0.0, now + TIME_PADDING).unwrap();
+    /// The VCAs the amp envelope drives, the sub's included
+    fn vcas(&self) -> Vec<AudioParam> {
+        let mut vcas = vec![self.gain.gain()];
+        if let Some(sub) = &self.sub {
+            vcas.push(sub.vca.gain());
+        }
+        vcas
+    }
+
+    /// Starts the amp envelope, peaking at `max_gain` for the oscillators and at `sub_gain` for the sub
+    pub fn amp_envelope_start(&mut self, ctx: &AudioContext, env: &Envelope, mut max_gain: f32, sub_gain: f32) {
+        let now = ctx.current_time();
+        max_gain /= self.unison as f32;
+        self.peak = max_gain;
+        self.silent_at = f64::INFINITY;
 
-        //Attack phase
-        let attack_time = TIME_PADDING + attack_s;
-        gain.linear_ramp_to_value_at_time(1.0, now + attack_time).unwrap();
+        for (gain, peak) in self.vcas().iter().zip(&[max_gain, sub_gain]) {
+            // Init envelope (Set value to current value and quickly ramp to 0 to avoid clicks)
+            gain.cancel_scheduled_values(now).unwrap();
+            gain.set_value_at_time(gain.value(), now).unwrap();
+            gain.linear_ramp_to_value_at_time(0.0, now + TIME_PADDING).unwrap();
 
-        //Decay phase (decay to sustain value)
-        let decay_time = TIME_PADDING + decay_s;
-        let sustain_value = env.sustain;
-        gain.set_target_at_time(sustain_value, now + attack_time, decay_time).unwrap();
+            env.start(gain, now + TIME_PADDING, 0.0, *peak, env.sustain * peak);
+        }
     }
 
-    pub fn amp_envelope_end(&self, ctx: &AudioContext, env: &Envelope) {
+    pub fn amp_envelope_end(&mut self, ctx: &AudioContext, env: &Envelope) {
         let now = ctx.current_time();
-        let release_s = env.release as f64 / 1000.0;
-        let gain: AudioParam = self.gain.gain();
-        //Release phase
-        gain.cancel_scheduled_values(now).unwrap();
-        gain.set_value_at_time(gain.value(), now).unwrap();
-        gain.set_target_at_time(0.0, now, TIME_PADDING + release_s).unwrap();
+        self.silent_at = now + env.release_time();
+        for gain in self.vcas() {
+            env.end(&gain, now, 0.0);
+        }
     }
 
-    pub fn filter_envelope_start(&self, ctx: &AudioContext, env: &Envelope, filter_frequency: u32) {
-        let attack_s = env.attack as f64 / 1000.0;
-        let decay_s = env.decay as f64 / 1000.0;
+    pub fn filter_envelope_start(&self, ctx: &AudioContext, env: &Envelope, amount: f32) {
+        let detune: AudioParam = self.filter.detune();
 
         // Init
         let now = ctx.current_time();
-        self.filter.detune().cancel_scheduled_values(now).unwrap();
-        self.filter.detune().set_value_at_time(self.filter.detune().value(), now).unwrap();
+        let from = detune.value();
+        detune.cancel_scheduled_values(now).unwrap();
+        detune.set_value_at_time(from, now).unwrap();
 
-        // Attack
-        let attack_time = TIME_PADDING + attack_s;
-        let target_frequency = FILTER_MAX_FREQ;
-        self.filter.detune().linear_ramp_to_value_at_time(target_frequency as f32, now + attack_time).unwrap();
-
-        // Decay
-        let decay_time = TIME_PADDING + decay_s;
+        let targets = filter_envelope_targets(self.filter_offset, amount, env.sustain);
+        env.start(&detune, now, from, targets.peak, targets.sustain);
+    }
 
-        // Calculate sustain
-        let cutoff = filter_frequency as f32;
-        let cutoff_pct = cutoff / (FILTER_MAX_FREQ as f32) * 100.0;
-        let min_sustain = (FILTER_MAX_FREQ as f32 / 100.0) * cutoff_pct;
-        let max_sustain = FILTER_MAX_FREQ as f32;
+    pub fn filter_envelope_end(&self, ctx: &AudioContext, env: &Envelope, amount: f32) {
+        let targets = filter_envelope_targets(self.filter_offset, amount, env.sustain);
+        env.end(&self.filter.detune(), ctx.current_time(), targets.rest);
+    }
 
-        let sustain_value = (env.sustain * (max_sustain - min_sustain) / 100.0) + min_sustain;
-        self.filter.detune().set_target_at_time(sustain_value, now + attack_time, decay_time).unwrap();
+    /// Where (0 to 1) the amp envelope is at
+    pub fn amp_level(&self) -> f32 {
+        if self.peak > 0.0 { self.gain.gain().value() / self.peak } else { 0.0 }
     }
 
-    pub fn filter_envelope_end(&self, ctx: &AudioContext, env: &Envelope, filter_frequency: u32) {
-        let now = ctx.current_time();
-        let release_s = env.release as f64 / 1000.0;
-        self.filter.detune().cancel_scheduled_values(now).unwrap();
-        self.filter.detune().set_value_at_time(self.filter.detune().value(), now).unwrap();
-        self.filter.detune().set_target_at_time(filter_frequency as f32, now, TIME_PADDING + release_s).unwrap();
+    /// Where the filter envelope is at, given its `amount` in cents
+    pub fn filter_level(&self, amount: f32) -> f32 {
+        filter_envelope_level(self.filter_offset, amount, self.filter.detune().value())
     }
 
     pub fn connect_to_audio(&self, to: &AudioNode) {
@@ -265,6 +562,24 @@ pub struct Oscillator {
     pub polyphony: usize,
     pub filter_frequency: u32,
     pub filter_resonance: f32,
+    pub filter_type: BiquadFilterType,
+    pub filter_env_amount: f32,
+    pub key_tracking: f32,
+    pub key_center: u8,
+    pub velocity_cutoff: f32,
+    pub velocity: Velocity,
+    pub channel_aftertouch: f32,
+    pub sync: bool,
+    pub sync_ratio: f32,
+    pub sub_type: OscillatorType,
+    pub sub_octave: u8,
+    pub sub_level: f32,
+    pub voice_mode: VoiceMode,
+    pub note_priority: NotePriority,
+    pub glide_mode: GlideMode,
+    pub glide_time: u32,
+    held_notes: NoteStack,
+    mono_note: Option<u8>,
     playing_notes: HashMap<u8, usize>,
     amp: GainNode,
     gain: Rc<RefCell<f32>>,
@@ -275,26 +590,45 @@ use std::cell::RefCell;
 
 impl Oscillator {
     pub fn new(name: String, ctx: AudioContext, polyphony: usize, unison: usize, filter_frequency: u32, filter_resonance: f32) -> Result<Oscillator, JsValue> {
+        let mut voices: Vec<Voice> = vec![];
+        for _ in 0..polyphony {
+            voices.push(Voice::new(&ctx, unison)?);
+        }
+        Oscillator::with_voices(name, ctx, voices, 0.9, filter_frequency, filter_resonance)
+    }
+
+    /// Creates a bank of voices fed by an external `source` rather than their own oscillators.
+    pub fn with_source(name: String, ctx: AudioContext, polyphony: usize, source: &AudioNode, gain: f32, filter_frequency: u32, filter_resonance: f32) -> Result<Oscillator, JsValue> {
+        let mut voices: Vec<Voice> = vec![];
+        for _ in 0..polyphony {
+            voices.push(Voice::with_source(&ctx, source)?);
+        }
+        Oscillator::with_voices(name, ctx, voices, gain, filter_frequency, filter_resonance)
+    }
+
+    fn with_voices(name: String, ctx: AudioContext, voices: Vec<Voice>, gain: f32, filter_frequency: u32, filter_resonance: f32) -> Result<Oscillator, JsValue> {
         let amp_env: Envelope = Default::default();
         let filter_env: Envelope = Default::default();
         let amp = ctx.create_gain()?;
+        let polyphony = voices.len();
 
-        let mut voices: Vec<Voice> = vec![];
-        for _ in 0..polyphony {
-            let v = Voice::new(&ctx, unison)?;
+        for v in &voices {
             v.connect_to_audio(&amp);
-            voices.push(v);
         }
 
-        let gain_control = Rc::new(RefCell::new(0.9));
+        let gain_control = Rc::new(RefCell::new(gain));
 
         let bus = unsafe { get_bus() };
         let control = gain_control.clone();
-        bus.control(format!("{}.gain", name), 0.9, Box::new(move |v| {
+        bus.control(format!("{}.gain", name), gain, Box::new(move |v| {
             let mut g = control.borrow_mut();
             *g = v;
         }));
 
+        voice_mod_control(&ctx, format!("{}.pitch", name), voices.iter().map(|v| v.pitch_mod.offset()).collect());
+        voice_mod_control(&ctx, format!("{}.cutoff", name), voices.iter().map(|v| v.cutoff_mod.offset()).collect());
+        voice_mod_control(&ctx, format!("{}.resonance", name), voices.iter().map(|v| v.resonance_mod.offset()).collect());
+
         let osc_type = OscillatorType::Sine;
 
         let mut o = Oscillator {
@@ -304,6 +638,24 @@ impl Oscillator {
             polyphony,
             filter_frequency,
             filter_resonance,
+            filter_type: BiquadFilterType::Lowpass,
+            filter_env_amount: FILTER_ENV_AMOUNT,
+            key_tracking: 0.0,
+            key_center: KEY_CENTER,
+            velocity_cutoff: 0.0,
+            velocity: Default::default(),
+            channel_aftertouch: 0.0,
+            sync: false,
+            sync_ratio: 1.0,
+            sub_type: OscillatorType::Square,
+            sub_octave: 1,
+            sub_level: 0.0,
+            voice_mode: VoiceMode::Poly,
+            note_priority: NotePriority::Last,
+            glide_mode: GlideMode::Always,
+            glide_time: 0,
+            held_notes: Default::default(),
+            mono_note: None,
             last_voice: 999,
             osc_type,
             ctx,
@@ -314,6 +666,8 @@ impl Oscillator {
         };
 
         o.set_waveform(osc_type);
+        o.set_filter_frequency(filter_frequency);
+        o.set_filter_resonance(filter_resonance);
 
         Ok(o)
     }
@@ -361,28 +715,182 @@ impl Oscillator {
         voice
     }
 
+    /// Cutoff offset in cents from key tracking and velocity
+    fn filter_offset(&self, note: u8, velocity: u8) -> f32 {
+        let level = self.velocity.level(velocity);
+        key_tracking(note, self.key_center, self.key_tracking) + level * self.velocity_cutoff
+    }
+
+    fn trigger(&mut self, idx: usize, velocity: u8) {
+        let g = *self.gain.borrow();
+        let level = self.velocity.level(velocity);
+        let amp_env = self.velocity.envelope(&self.amp_env, level);
+        let filter_env = self.velocity.envelope(&self.filter_env, level);
+        let voice = &mut self.voices[idx];
+        voice.velocity = level;
+        voice.random = js_sys::Math::random() as f32 * 2.0 - 1.0;
+        voice.aftertouch = self.channel_aftertouch;
+        voice.aftertouch_target = self.channel_aftertouch;
+        let amp = self.velocity.amp(level);
+        voice.amp_envelope_start(&self.ctx, &amp_env, g * amp, amp);
+        voice.filter_envelope_start(&self.ctx, &filter_env, self.filter_env_amount);
+        voice.start_sync_ratio(&self.ctx, self.velocity.sync_ratio(self.sync_ratio, level));
+    }
+
+    /// Sets every voice's aftertouch (0 to 1)
+    pub fn aftertouch(&mut self, value: f32) {
+        self.channel_aftertouch = value;
+        for v in &mut self.voices {
+            v.aftertouch_target = value;
+        }
+    }
+
+    /// Sets the aftertouch (0 to 1) of the voice playing `note`, if it's sounding
+    pub fn poly_aftertouch(&mut self, note: u8, value: f32) {
+        let idx = if self.voice_mode == VoiceMode::Poly {
+            self.playing_notes.get(&note).cloned()
+        } else if self.mono_note == Some(note) {
+            Some(0)
+        } else {
+            None
+        };
+        if let Some(idx) = idx {
+            self.voices[idx].aftertouch_target = value;
+        }
+    }
+
+    /// Moves every voice's aftertouch `elapsed` seconds closer to where it's been set
+    pub fn smooth_aftertouch(&mut self, elapsed: f64) {
+        let k = 1.0 - (-elapsed / AFTERTOUCH_SMOOTHING).exp() as f32;
+        for v in &mut self.voices {
+            v.aftertouch += (v.aftertouch_target - v.aftertouch) * k;
+        }
+    }
+
+    /// How many voices are sounding, counting those still releasing
+    pub fn sounding(&self) -> usize {
+        let now = self.ctx.current_time();
+        self.voices.iter().filter(|v| v.silent_at > now).count()
+    }
+
+    /// The voice that was played last
+    pub fn last_voice(&self) -> usize {
+        if self.voice_mode != VoiceMode::Poly || self.last_voice == 999 { 0 } else { self.last_voice }
+    }
+
+    /// Values of the per-voice modulation sources for voice `idx`
+    pub fn voice_sources(&self, idx: usize) -> VoiceSources {
+        let voice = &self.voices[idx];
+        VoiceSources {
+            amp_env: voice.amp_level(),
+            filter_env: voice.filter_level(self.filter_env_amount),
+            velocity: voice.velocity,
+            key: (voice.note as f32 - self.key_center as f32) / 12.0,
+            aftertouch: voice.aftertouch,
+            random: voice.random,
+        }
+    }
+
+    fn release(&mut self, idx: usize) {
+        let voice = &mut self.voices[idx];
+        voice.amp_envelope_end(&self.ctx, &self.amp_env);
+        voice.filter_envelope_end(&self.ctx, &self.filter_env, self.filter_env_amount);
+    }
+
     pub fn note_on(&mut self, note: u8, velocity: u8) {
+        if self.voice_mode != VoiceMode::Poly {
+            return self.mono_note_on(note, velocity);
+        }
         let current_voice = self.get_voice();
         self.last_voice = current_voice;
         self.playing_notes.insert(note, current_voice);
 
+        let offset = self.filter_offset(note, velocity);
         let voice = &mut self.voices[current_voice];
+        voice.note = note;
         voice.set_freq(&self.ctx, midi_to_freq(note));
-        let g = self.gain.borrow();
-        voice.amp_envelope_start(&self.ctx, &self.amp_env, *g, velocity);
-        voice.filter_envelope_start(&self.ctx, &self.filter_env, self.filter_frequency);
+        voice.set_filter_offset(offset);
+        self.trigger(current_voice, velocity);
     }
 
     pub fn note_off(&mut self, note: u8) {
-        match self.playing_notes.get(&note) {
-            None => { panic!("Note off without note on") },
-            Some(idx) => {
-                let voice = &self.voices[*idx];
-                voice.amp_envelope_end(&self.ctx, &self.amp_env);
-                voice.filter_envelope_end(&self.ctx, &self.filter_env, self.filter_frequency);
-                self.playing_notes.remove(&note);
-            }
+        if self.voice_mode != VoiceMode::Poly {
+            return self.mono_note_off(note);
+        }
+        // Notes already released by a voice mode switch have nothing left to release
+        if let Some(idx) = self.playing_notes.remove(&note) {
+            self.release(idx);
+        }
+    }
+
+    fn mono_note_on(&mut self, note: u8, velocity: u8) {
+        let legato = !self.held_notes.is_empty();
+        self.held_notes.push(note, velocity);
+        self.play_mono(legato);
+    }
+
+    fn mono_note_off(&mut self, note: u8) {
+        if !self.held_notes.remove(note) {
+            return;
+        }
+        if self.held_notes.is_empty() {
+            self.release(0);
+        } else {
+            // Return to the previous held note
+            self.play_mono(true);
+        }
+    }
+
+    /// Moves the single voice to whichever held note has priority
+    fn play_mono(&mut self, legato: bool) {
+        let (note, velocity) = match self.held_notes.current(self.note_priority) {
+            Some(n) => n,
+            None => return,
+        };
+        if legato && self.mono_note == Some(note) {
+            return;
         }
+        let glide = self.glide_time > 0 && self.mono_note.is_some()
+            && (legato || self.glide_mode == GlideMode::Always);
+        self.mono_note = Some(note);
+
+        let freq = midi_to_freq(note);
+        self.voices[0].note = note;
+        if glide {
+            self.voices[0].glide_freq(&self.ctx, freq, self.glide_time as f64 / 1000.0);
+        } else {
+            self.voices[0].set_freq(&self.ctx, freq);
+        }
+        if !legato || self.voice_mode == VoiceMode::Mono {
+            let offset = self.filter_offset(note, velocity);
+            self.voices[0].set_filter_offset(offset);
+            self.trigger(0, velocity);
+        }
+    }
+
+    /// Switching modes releases every sounding note
+    pub fn set_voice_mode(&mut self, mode: VoiceMode) {
+        let sounding: Vec<usize> = self.playing_notes.drain().map(|(_, idx)| idx).collect();
+        for idx in sounding {
+            self.release(idx);
+        }
+        if !self.held_notes.is_empty() {
+            self.held_notes.clear();
+            self.release(0);
+        }
+        self.voice_mode = mode;
+    }
+
+    pub fn set_note_priority(&mut self, priority: NotePriority) {
+        self.note_priority = priority;
+    }
+
+    pub fn set_glide_time(&mut self, ms: u32) {
+        self.glide_time = ms;
+    }
+
+    pub fn set_glide_mode(&mut self, mode: GlideMode) {
+        self.glide_mode = mode;
     }
 
     pub fn set_waveform(&mut self, waveform: OscillatorType) {
@@ -392,31 +900,102 @@ impl Oscillator {
         }
     }
 
-    pub fn set_amp_attack(&mut self, v: u32) {
+    /// Gives every voice a sub oscillator, unaffected by the bank's filter and gain. Must be called before `on`.
+    pub fn add_sub(&mut self) -> Result<(), JsValue> {
+        for v in &mut self.voices {
+            v.add_sub(&self.ctx, self.sub_type, self.sub_octave, self.sub_level, &self.amp)?;
+        }
+        Ok(())
+    }
+
+    pub fn set_sub_type(&mut self, waveform: OscillatorType) {
+        self.sub_type = waveform;
+        for v in &self.voices {
+            v.set_sub_waveform(waveform);
+        }
+    }
+
+    pub fn set_sub_octave(&mut self, octave: u8) {
+        self.sub_octave = octave;
+        for v in &mut self.voices {
+            v.set_sub_octave(&self.ctx, octave);
+        }
+    }
+
+    pub fn set_sub_level(&mut self, level: f32) {
+        self.sub_level = level;
+        for v in &self.voices {
+            v.set_sub_level(&self.ctx, level);
+        }
+    }
+
+    /// Creates every voice's hard-synced oscillators ahead of enabling sync, failing if the worklets aren't loaded
+    pub fn prepare_sync(&mut self) -> Result<(), JsValue> {
+        for v in &mut self.voices {
+            v.prepare_sync(&self.ctx, self.osc_type, self.sync_ratio)?;
+        }
+        Ok(())
+    }
+
+    pub fn set_sync(&mut self, enabled: bool) -> Result<(), JsValue> {
+        for v in &mut self.voices {
+            v.set_sync(&self.ctx, enabled, self.osc_type, self.sync_ratio)?;
+        }
+        self.sync = enabled;
+        Ok(())
+    }
+
+    pub fn set_sync_ratio(&mut self, ratio: f32) {
+        self.sync_ratio = ratio;
+        for v in &self.voices {
+            v.set_sync_ratio(&self.ctx, self.velocity.sync_ratio(ratio, v.velocity));
+        }
+    }
+
+    pub fn set_amp_delay(&mut self, v: f32) {
+        self.amp_env.delay = v;
+    }
+    pub fn set_amp_attack(&mut self, v: f32) {
         self.amp_env.attack = v;
     }
-    pub fn set_amp_decay(&mut self, v: u32) {
+    pub fn set_amp_hold(&mut self, v: f32) {
+        self.amp_env.hold = v;
+    }
+    pub fn set_amp_decay(&mut self, v: f32) {
         self.amp_env.decay = v;
     }
     pub fn set_amp_sustain(&mut self, v: f32) {
         self.amp_env.sustain = v;
     }
-    pub fn set_amp_release(&mut self, v: u32) {
+    pub fn set_amp_release(&mut self, v: f32) {
         self.amp_env.release = v;
     }
 
-    pub fn set_filter_attack(&mut self, v: u32) {
+    pub fn set_filter_attack(&mut self, v: f32) {
         self.filter_env.attack = v;
     }
-    pub fn set_filter_decay(&mut self, v: u32) {
+    pub fn set_filter_decay(&mut self, v: f32) {
         self.filter_env.decay = v;
     }
     pub fn set_filter_sustain(&mut self, v: f32) {
         self.filter_env.sustain = v;
     }
-    pub fn set_filter_release(&mut self, v: u32) {
+    pub fn set_filter_release(&mut self, v: f32) {
         self.filter_env.release = v;
     }
+    pub fn set_filter_delay(&mut self, v: f32) {
+        self.filter_env.delay = v;
+    }
+    pub fn set_filter_hold(&mut self, v: f32) {
+        self.filter_env.hold = v;
+    }
+
+    pub fn set_amp_curve(&mut self, stage: EnvelopeStage, shape: CurveShape, tension: f32) {
+        self.amp_env.set_curve(stage, shape, tension);
+    }
+    pub fn set_filter_curve(&mut self, stage: EnvelopeStage, shape: CurveShape, tension: f32) {
+        self.filter_env.set_curve(stage, shape, tension);
+    }
 
     pub fn set_filter_frequency(&mut self, f: u32) {
         self.filter_frequency = f;
@@ -425,10 +1004,35 @@ impl Oscillator {
         }
     }
 
+    pub fn set_filter_env_amount(&mut self, cents: f32) {
+        self.filter_env_amount = cents;
+    }
+
+    pub fn set_key_tracking(&mut self, amount: f32) {
+        self.key_tracking = amount;
+    }
+
+    pub fn set_key_center(&mut self, note: u8) {
+        self.key_center = note;
+    }
+
+    pub fn set_velocity_cutoff(&mut self, cents: f32) {
+        self.velocity_cutoff = cents;
+    }
+
+    pub fn set_filter_type(&mut self, filter_type: BiquadFilterType) {
+        self.filter_type = filter_type;
+        for v in &self.voices {
+            v.set_filter_type(filter_type);
+        }
+        self.set_filter_resonance(self.filter_resonance);
+    }
+
     pub fn set_filter_resonance(&mut self, q: f32) {
         self.filter_resonance = q;
-        for v in &mut self.voices {
-            v.set_filter_resonance(&self.ctx, q);
+        let (q, gain) = filter_q(self.filter_type, q);
+        for v in &self.voices {
+            v.set_filter_q(&self.ctx, q, gain);
         }
     }
 
@@ -438,260 +1042,1918 @@ impl Oscillator {
     }
   }
 
+/// How a channel's level is split between left and right as it's panned
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PanLaw {
+    /// Only turns down the side being panned away from, 0 dB in the center
+    Balance = 0,
+    /// -3 dB in the center, keeping the power constant
+    ConstantPower = 1,
+    /// -4.5 dB in the center
+    Compromise = 2,
+    /// -6 dB in the center, keeping the amplitude constant
+    Linear = 3,
+}
+
+/// Left and right gains for `pan` (-1 to 1) following `law`
+pub fn pan_gains(law: PanLaw, pan: f32) -> (f32, f32) {
+    let x = (pan.max(-1.0).min(1.0) + 1.0) / 2.0;
+    let angle = x * std::f32::consts::FRAC_PI_2;
+    match law {
+        PanLaw::Balance => ((2.0 * (1.0 - x)).min(1.0), (2.0 * x).min(1.0)),
+        PanLaw::ConstantPower => (angle.cos(), angle.sin()),
+        PanLaw::Compromise => (((1.0 - x) * angle.cos()).sqrt(), (x * angle.sin()).sqrt()),
+        PanLaw::Linear => (1.0 - x, x),
+    }
+}
+
+/// A channel's feed into an aux bus
+struct Send {
+    level: GainNode,
+    /// Taken after the inserts but before the fader, pan and mute rather than after them
+    pre: bool,
+}
+
 #[wasm_bindgen]
 pub struct Channel {
     ctx: AudioContext,
+    /// Names its bus controls, staying the same as channels get moved around
+    id: usize,
+    name: String,
+    input: GainNode,
+    inserts: InsertChain,
+    /// The fader
     gain: GainNode,
+    left: GainNode,
+    right: GainNode,
+    /// Lets the channel through unless it's muted or another one is soloed
+    gate: GainNode,
+    /// Does the same for pre-fader sends, which are taken after the inserts
+    pre_gate: GainNode,
+    pan: f32,
+    pan_law: PanLaw,
+    muted: bool,
+    soloed: bool,
+    /// Keeps playing while other channels are soloed, like aux returns
+    solo_safe: bool,
+    /// One per aux bus
+    sends: Vec<Send>,
+    /// Measures the channel's output
+    meter: Meter,
 }
 
 #[wasm_bindgen]
 impl Channel {
     pub fn new(ctx: AudioContext) -> Result<Channel, JsValue> {
+        // Mono inputs get spread over both sides before panning
+        let input = ctx.create_gain()?;
+        input.set_channel_count(2);
+        input.set_channel_count_mode(ChannelCountMode::Explicit);
+        input.set_channel_interpretation(ChannelInterpretation::Speakers);
+
         let gain = ctx.create_gain()?;
         gain.gain().set_value_at_time(0.0, ctx.current_time())?; // start off
-        Ok(Channel {
+
+        let splitter = ctx.create_channel_splitter_with_number_of_outputs(2)?;
+        let merger = ctx.create_channel_merger_with_number_of_inputs(2)?;
+        let left = ctx.create_gain()?;
+        let right = ctx.create_gain()?;
+        let gate = ctx.create_gain()?;
+        let pre_gate = ctx.create_gain()?;
+        let inserts = InsertChain::new(ctx.clone())?;
+        input.connect_with_audio_node(&inserts.input())?;
+        inserts.output().connect_with_audio_node(&gain)?;
+        inserts.output().connect_with_audio_node(&pre_gate)?;
+        gain.connect_with_audio_node(&splitter)?;
+        splitter.connect_with_audio_node_and_output(&left, 0)?;
+        splitter.connect_with_audio_node_and_output(&right, 1)?;
+        left.connect_with_audio_node_and_output_and_input(&merger, 0, 0)?;
+        right.connect_with_audio_node_and_output_and_input(&merger, 0, 1)?;
+        merger.connect_with_audio_node(&gate)?;
+        let meter = Meter::new(&ctx, &gate)?;
+
+        let mut c = Channel {
             ctx,
+            id: 0,
+            name: String::new(),
+            input,
+            inserts,
             gain,
-        })
+            left,
+            right,
+            gate,
+            pre_gate,
+            pan: 0.0,
+            pan_law: PanLaw::ConstantPower,
+            muted: false,
+            soloed: false,
+            solo_safe: false,
+            sends: vec![],
+            meter,
+        };
+        c.set_pan(0.0);
+        Ok(c)
     }
 
     #[wasm_bindgen]
     pub fn set_gain(&self, gain: f32) {
-        self.gain.gain().set_value_at_time(gain, self.ctx.current_time()).unwrap();
+        smooth::set(&self.gain.gain(), gain, self.ctx.current_time(), smooth::GAIN);
     }
-}
 
-#[wasm_bindgen]
-pub struct Mixer {
-    channels: Vec<Channel>,
-    ctx: AudioContext,
-    master: Channel,
-}
+    /// Pans the channel from -1 (left) to 1 (right)
+    #[wasm_bindgen]
+    pub fn set_pan(&mut self, pan: f32) {
+        self.pan = pan;
+        let (l, r) = pan_gains(self.pan_law, pan);
+        let now = self.ctx.current_time();
+        smooth::set(&self.left.gain(), l, now, smooth::GAIN);
+        smooth::set(&self.right.gain(), r, now, smooth::GAIN);
+    }
 
-#[wasm_bindgen]
-impl Mixer {
-    #[wasm_bindgen(constructor)]
-    pub fn new(ctx: AudioContext, channel_count: u8) -> Result<Mixer, JsValue> {
-        let master = Channel::new(ctx.clone()).unwrap();
+    #[wasm_bindgen]
+    pub fn set_pan_law(&mut self, law: PanLaw) {
+        self.pan_law = law;
+        self.set_pan(self.pan);
+    }
 
-        let channels: Vec<Channel> = (0..channel_count).map(|_| {
-            let c = Channel::new(ctx.clone()).unwrap();
-            audio::connect(&c, &master);
-            c
-        }).collect();
+    /// Adds a send, off and post-fader, feeding `to`
+    fn add_send(&mut self, to: &AudioNode) -> Result<(), JsValue> {
+        let level = self.ctx.create_gain()?;
+        level.gain().set_value_at_time(0.0, self.ctx.current_time())?;
+        self.tap(false).connect_with_audio_node(&level)?;
+        level.connect_with_audio_node(to)?;
+        self.sends.push(Send { level, pre: false });
+        Ok(())
+    }
 
-        let m = Mixer {
-            channels,
-            master,
-            ctx,
-        };
+    fn set_send(&self, aux: usize, level: f32) {
+        let send = &self.sends[aux];
+        smooth::set(&send.level.gain(), level, self.ctx.current_time(), smooth::GAIN);
+    }
 
-        m.set_master_gain(0.9);
-        for idx in 0..channel_count {
-            m.set_gain(idx as usize, 0.8);
+    /// Moves the send on `aux` before or after the fader
+    fn set_send_pre(&mut self, aux: usize, pre: bool) {
+        if self.sends[aux].pre == pre {
+            return;
         }
-
-        Ok(m)
+        let level = self.sends[aux].level.clone();
+        self.tap(!pre).disconnect_with_audio_node(&level).unwrap();
+        self.tap(pre).connect_with_audio_node(&level).unwrap();
+        self.sends[aux].pre = pre;
     }
 
-    #[wasm_bindgen]
-    pub fn set_master_gain(&self, gain: f32) {
-        self.master.set_gain(gain);
+    /// Where pre- or post-fader sends are taken from, pre-fader being after the inserts.
+    /// Both are silenced by mute and solo.
+    fn tap(&self, pre: bool) -> AudioNode {
+        if pre {
+            self.pre_gate.clone().into()
+        } else {
+            self.gate.clone().into()
+        }
     }
 
+    fn set_audible(&self, audible: bool) {
+        let level = if audible { 1.0 } else { 0.0 };
+        let now = self.ctx.current_time();
+        smooth::set(&self.gate.gain(), level, now, smooth::GAIN);
+        smooth::set(&self.pre_gate.gain(), level, now, smooth::GAIN);
+    }
+}
+
+impl Drop for Channel {
+    /// Cuts the channel off, leaving whatever still feeds it playing into nothing
+    fn drop(&mut self) {
+        self.input.disconnect().unwrap();
+        self.gate.disconnect().unwrap();
+        for send in &self.sends {
+            send.level.disconnect().unwrap();
+        }
+    }
+}
+
+/// The channels, aux returns and master of a mixer, shared with its bus controls
+struct Strips {
+    channels: Vec<Channel>,
+    auxes: Vec<Channel>,
+    master: Channel,
+    /// Id of the next channel added
+    next_id: usize,
+}
+
+/// A channel by id, an aux return or the master
+#[derive(Clone, Copy)]
+enum Strip {
+    Channel(usize),
+    Aux(usize),
+    Master,
+}
+
+impl Strip {
+    /// Prefix of the strip's bus controls
+    fn id(&self) -> String {
+        match self {
+            Strip::Channel(id) => format!("mixer.{}", id),
+            Strip::Aux(idx) => format!("mixer.aux{}", idx),
+            Strip::Master => "mixer.master".to_string(),
+        }
+    }
+}
+
+impl Strips {
+    fn get(&mut self, strip: Strip) -> Option<&mut Channel> {
+        match strip {
+            Strip::Channel(id) => self.channels.iter_mut().find(|c| c.id == id),
+            Strip::Aux(idx) => self.auxes.get_mut(idx),
+            Strip::Master => Some(&mut self.master),
+        }
+    }
+
+    /// Every strip, master included
+    fn all_mut(&mut self) -> impl Iterator<Item = &mut Channel> {
+        self.channels.iter_mut().chain(self.auxes.iter_mut()).chain(Some(&mut self.master))
+    }
+
+    /// The insert chain holding the effect named `name`
+    fn chain(&mut self, name: &str) -> Option<&mut InsertChain> {
+        self.all_mut()
+            .map(|c| &mut c.inserts)
+            .find(|i| i.position(name).is_some())
+    }
+
+    fn insert(&mut self, name: &str) -> Option<&mut InsertSlot> {
+        self.chain(name).and_then(|i| i.get_mut(name))
+    }
+
+    fn insert_names(&self) -> Vec<String> {
+        self.channels.iter().chain(&self.auxes).chain(Some(&self.master))
+            .flat_map(|c| c.inserts.slots.iter().map(|s| s.name.clone()))
+            .collect()
+    }
+
+    /// Solo in place: with any channel soloed, only the soloed and solo safe ones play.
+    /// Muting wins over soloing.
+    fn update_gates(&self) {
+        let any_solo = self.channels.iter().chain(&self.auxes).any(|c| c.soloed);
+        for c in self.channels.iter().chain(&self.auxes) {
+            c.set_audible(!c.muted && (c.soloed || c.solo_safe || !any_solo));
+        }
+    }
+
+    /// Registers the gain, pan, mute and solo controls of `strip`
+    fn register(strips: &Rc<RefCell<Strips>>, strip: Strip) {
+        let bus = unsafe { get_bus() };
+        let id = strip.id();
+        let s = strips.clone();
+        bus.control(format!("{}.gain", id), 0.0, Box::new(move |v| {
+            if let Some(c) = s.borrow_mut().get(strip) {
+                c.set_gain(v);
+            }
+        }));
+        let s = strips.clone();
+        bus.control(format!("{}.pan", id), 0.0, Box::new(move |v| {
+            if let Some(c) = s.borrow_mut().get(strip) {
+                c.set_pan(v);
+            }
+        }));
+        let s = strips.clone();
+        bus.control(format!("{}.mute", id), 0.0, Box::new(move |v| {
+            let mut strips = s.borrow_mut();
+            if let Some(c) = strips.get(strip) {
+                c.muted = v > 0.5;
+            }
+            strips.update_gates();
+        }));
+        let s = strips.clone();
+        bus.control(format!("{}.solo", id), 0.0, Box::new(move |v| {
+            let mut strips = s.borrow_mut();
+            if let Some(c) = strips.get(strip) {
+                c.soloed = v > 0.5;
+            }
+            strips.update_gates();
+        }));
+    }
+
+    /// Registers the level and pre-fader controls of channel `id`'s send to `aux`
+    fn register_send(strips: &Rc<RefCell<Strips>>, id: usize, aux: usize) {
+        let bus = unsafe { get_bus() };
+        let s = strips.clone();
+        bus.control(format!("mixer.{}.send{}", id, aux), 0.0, Box::new(move |v| {
+            if let Some(c) = s.borrow_mut().get(Strip::Channel(id)) {
+                c.set_send(aux, v);
+            }
+        }));
+        let s = strips.clone();
+        bus.control(format!("mixer.{}.send{}.pre", id, aux), 0.0, Box::new(move |v| {
+            if let Some(c) = s.borrow_mut().get(Strip::Channel(id)) {
+                c.set_send_pre(aux, v > 0.5);
+            }
+        }));
+    }
+}
+
+/// Mixes channels into a master channel, through a safety limiter.
+///
+/// Channels are addressed by index, which changes as they're added, removed and moved.
+/// Their state lives on the bus under their id instead (see `get_channel_id`), which
+/// stays the same: `mixer.<id>.gain`, `.pan` (-1 to 1), `.mute` and `.solo` (on above 0.5).
+/// The master's is `mixer.master.gain`. Aux returns have the same controls as
+/// `mixer.aux<aux>.*`, and each channel's sends are `mixer.<id>.send<aux>`, pre-fader
+/// when `mixer.<id>.send<aux>.pre` is on.
+#[wasm_bindgen]
+pub struct Mixer {
+    strips: Rc<RefCell<Strips>>,
+    ctx: AudioContext,
+    limiter: Limiter,
+}
+
+#[wasm_bindgen]
+impl Mixer {
+    #[wasm_bindgen(constructor)]
+    pub fn new(ctx: AudioContext, channel_count: u8) -> Result<Mixer, JsValue> {
+        let bus = unsafe { get_bus() };
+        let master = Channel::new(ctx.clone()).unwrap();
+        let limiter = Limiter::new(&ctx)?;
+        audio::connect(&master, &limiter);
+
+        let strips = Rc::new(RefCell::new(Strips { channels: vec![], auxes: vec![], master, next_id: 0 }));
+
+        let s = strips.clone();
+        bus.control("mixer.master.gain".to_string(), 0.0, Box::new(move |v| s.borrow().master.set_gain(v)));
+
+        let mut m = Mixer {
+            strips,
+            limiter,
+            ctx,
+        };
+
+        m.set_master_gain(0.9);
+        for idx in 0..channel_count {
+            m.add_channel(format!("Channel {}", idx + 1))?;
+        }
+
+        Ok(m)
+    }
+
+    /// Adds a channel at the end, returning its index
+    #[wasm_bindgen]
+    pub fn add_channel(&mut self, name: String) -> Result<usize, JsValue> {
+        let bus = unsafe { get_bus() };
+        let (id, idx, aux_count) = {
+            let mut strips = self.strips.borrow_mut();
+            let mut c = Channel::new(self.ctx.clone())?;
+            c.id = strips.next_id;
+            c.name = name;
+            c.set_pan_law(strips.master.pan_law);
+            audio::connect(&c, &strips.master);
+            for ret in &strips.auxes {
+                c.add_send(&ret.input())?;
+            }
+            strips.next_id += 1;
+            strips.channels.push(c);
+            strips.update_gates();
+            (strips.next_id - 1, strips.channels.len() - 1, strips.auxes.len())
+        };
+
+        Strips::register(&self.strips, Strip::Channel(id));
+        for aux in 0..aux_count {
+            Strips::register_send(&self.strips, id, aux);
+        }
+        bus.trigger(format!("mixer.{}.gain", id), 0.8);
+
+        Ok(idx)
+    }
+
+    /// Removes channel `idx`, along with its inserts and controls
+    #[wasm_bindgen]
+    pub fn remove_channel(&mut self, idx: usize) -> Result<(), JsValue> {
+        let bus = unsafe { get_bus() };
+        let id = self.channel_id(idx)?;
+        let channel = {
+            let mut strips = self.strips.borrow_mut();
+            let channel = strips.channels.remove(idx);
+            strips.update_gates();
+            channel
+        };
+        bus.remove(&format!("mixer.{}.", id));
+        drop(channel);
+        Ok(())
+    }
+
+    /// Moves channel `idx` to index `to`, shifting the ones in between
+    #[wasm_bindgen]
+    pub fn move_channel(&mut self, idx: usize, to: usize) -> Result<(), JsValue> {
+        self.channel_id(idx)?;
+        self.channel_id(to)?;
+        let mut strips = self.strips.borrow_mut();
+        let channel = strips.channels.remove(idx);
+        strips.channels.insert(to, channel);
+        Ok(())
+    }
+
+    #[wasm_bindgen]
+    pub fn channel_count(&self) -> usize {
+        self.strips.borrow().channels.len()
+    }
+
+    /// Id of channel `idx`, naming its bus controls
+    #[wasm_bindgen]
+    pub fn get_channel_id(&self, idx: usize) -> Result<usize, JsValue> {
+        self.channel_id(idx)
+    }
+
+    #[wasm_bindgen]
+    pub fn set_channel_name(&self, idx: usize, name: String) -> Result<(), JsValue> {
+        self.channel_id(idx)?;
+        self.strips.borrow_mut().channels[idx].name = name;
+        Ok(())
+    }
+
+    #[wasm_bindgen]
+    pub fn get_channel_name(&self, idx: usize) -> Result<String, JsValue> {
+        self.channel_id(idx)?;
+        Ok(self.strips.borrow().channels[idx].name.clone())
+    }
+
+    /// Adds an aux bus, returning its index. Every channel gets a send to it, off and post-fader,
+    /// and its return goes into the master, solo safe.
+    #[wasm_bindgen]
+    pub fn add_aux(&mut self) -> Result<usize, JsValue> {
+        let bus = unsafe { get_bus() };
+        let aux = {
+            let mut strips = self.strips.borrow_mut();
+            let mut ret = Channel::new(self.ctx.clone())?;
+            ret.solo_safe = true;
+            ret.set_pan_law(strips.master.pan_law);
+            audio::connect(&ret, &strips.master);
+            for c in strips.channels.iter_mut() {
+                c.add_send(&ret.input())?;
+            }
+            strips.auxes.push(ret);
+            strips.auxes.len() - 1
+        };
+
+        Strips::register(&self.strips, Strip::Aux(aux));
+        let ids: Vec<usize> = self.strips.borrow().channels.iter().map(|c| c.id).collect();
+        for id in ids {
+            Strips::register_send(&self.strips, id, aux);
+        }
+        bus.trigger(format!("mixer.aux{}.gain", aux), 0.8);
+
+        Ok(aux)
+    }
+
+    #[wasm_bindgen]
+    pub fn aux_count(&self) -> usize {
+        self.strips.borrow().auxes.len()
+    }
+
+    /// Sets how much (0 to 1) of channel `idx` goes to aux bus `aux`
+    #[wasm_bindgen]
+    pub fn set_send(&self, idx: usize, aux: usize, level: f32) -> Result<(), JsValue> {
+        self.set(idx, &format!("send{}", aux), level)
+    }
+
+    #[wasm_bindgen]
+    pub fn get_send(&self, idx: usize, aux: usize) -> Result<f32, JsValue> {
+        self.get(idx, &format!("send{}", aux))
+    }
+
+    /// Takes channel `idx`'s send to `aux` before its fader, pan and mute
+    #[wasm_bindgen]
+    pub fn set_send_pre(&self, idx: usize, aux: usize, pre: bool) -> Result<(), JsValue> {
+        self.set(idx, &format!("send{}.pre", aux), if pre { 1.0 } else { 0.0 })
+    }
+
+    #[wasm_bindgen]
+    pub fn get_send_pre(&self, idx: usize, aux: usize) -> Result<bool, JsValue> {
+        Ok(self.get(idx, &format!("send{}.pre", aux))? > 0.5)
+    }
+
+    #[wasm_bindgen]
+    pub fn set_aux_gain(&self, aux: usize, gain: f32) {
+        let bus = unsafe { get_bus() };
+        bus.trigger(format!("mixer.aux{}.gain", aux), gain);
+    }
+
+    #[wasm_bindgen]
+    pub fn get_aux_gain(&self, aux: usize) -> f32 {
+        let bus = unsafe { get_bus() };
+        bus.value(format!("mixer.aux{}.gain", aux))
+    }
+
+    #[wasm_bindgen]
+    pub fn set_aux_mute(&self, aux: usize, muted: bool) {
+        let bus = unsafe { get_bus() };
+        bus.trigger(format!("mixer.aux{}.mute", aux), if muted { 1.0 } else { 0.0 });
+    }
+
+    #[wasm_bindgen]
+    pub fn get_aux_mute(&self, aux: usize) -> bool {
+        let bus = unsafe { get_bus() };
+        bus.value(format!("mixer.aux{}.mute", aux)) > 0.5
+    }
+
+    #[wasm_bindgen]
+    pub fn set_master_gain(&self, gain: f32) {
+        let bus = unsafe { get_bus() };
+        bus.trigger("mixer.master.gain".to_string(), gain);
+    }
+
+    #[wasm_bindgen]
+    pub fn set_gain(&self, idx: usize, gain: f32) -> Result<(), JsValue> {
+        self.set(idx, "gain", gain)
+    }
+
+    #[wasm_bindgen]
+    pub fn get_gain(&self, idx: usize) -> Result<f32, JsValue> {
+        self.get(idx, "gain")
+    }
+
+    #[wasm_bindgen]
+    pub fn set_pan(&self, idx: usize, pan: f32) -> Result<(), JsValue> {
+        self.set(idx, "pan", pan)
+    }
+
+    #[wasm_bindgen]
+    pub fn get_pan(&self, idx: usize) -> Result<f32, JsValue> {
+        self.get(idx, "pan")
+    }
+
+    #[wasm_bindgen]
+    pub fn set_mute(&self, idx: usize, muted: bool) -> Result<(), JsValue> {
+        self.set(idx, "mute", if muted { 1.0 } else { 0.0 })
+    }
+
+    #[wasm_bindgen]
+    pub fn get_mute(&self, idx: usize) -> Result<bool, JsValue> {
+        Ok(self.get(idx, "mute")? > 0.5)
+    }
+
+    #[wasm_bindgen]
+    pub fn set_solo(&self, idx: usize, soloed: bool) -> Result<(), JsValue> {
+        self.set(idx, "solo", if soloed { 1.0 } else { 0.0 })
+    }
+
+    #[wasm_bindgen]
+    pub fn get_solo(&self, idx: usize) -> Result<bool, JsValue> {
+        Ok(self.get(idx, "solo")? > 0.5)
+    }
+
+    /// Sets the pan law of every channel
+    #[wasm_bindgen]
+    pub fn set_pan_law(&self, law: PanLaw) {
+        for c in self.strips.borrow_mut().all_mut() {
+            c.set_pan_law(law);
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn get_pan_law(&self) -> PanLaw {
+        self.strips.borrow().master.pan_law
+    }
+
+    /// Adds an effect of type `kind` (see `effect_types`) at the end of channel `idx`'s inserts,
+    /// returning its position. Its controls are named after `name`, which must be unique.
+    #[wasm_bindgen]
+    pub fn add_insert(&mut self, idx: usize, kind: &str, name: String) -> Result<usize, JsValue> {
+        let id = self.channel_id(idx)?;
+        self.add_insert_to(Strip::Channel(id), kind, name)
+    }
+
+    /// Adds an effect at the end of aux bus `aux`'s inserts, to be shared by the channels sending to it
+    #[wasm_bindgen]
+    pub fn add_aux_insert(&mut self, aux: usize, kind: &str, name: String) -> Result<usize, JsValue> {
+        self.add_insert_to(Strip::Aux(aux), kind, name)
+    }
+
+    /// Adds an effect at the end of the master's inserts, ahead of the limiter
+    #[wasm_bindgen]
+    pub fn add_master_insert(&mut self, kind: &str, name: String) -> Result<usize, JsValue> {
+        self.add_insert_to(Strip::Master, kind, name)
+    }
+
+    /// Fades the insert named `name` out and removes it on a later `tick`
+    #[wasm_bindgen]
+    pub fn remove_insert(&mut self, name: &str) -> Result<(), JsValue> {
+        self.strips.borrow_mut().chain(name).ok_or_else(|| no_insert(name))?.remove(name);
+        Ok(())
+    }
+
+    /// Fades the insert named `name` out and moves it to position `to` in its chain on a later `tick`
+    #[wasm_bindgen]
+    pub fn move_insert(&mut self, name: &str, to: usize) -> Result<(), JsValue> {
+        self.strips.borrow_mut().chain(name).ok_or_else(|| no_insert(name))?.move_to(name, to);
+        Ok(())
+    }
+
+    /// Names of the effects on channel `idx`'s inserts, in order
+    #[wasm_bindgen]
+    pub fn get_inserts(&self, idx: usize) -> Result<js_sys::Array, JsValue> {
+        let id = self.channel_id(idx)?;
+        Ok(self.insert_names(Strip::Channel(id)))
+    }
+
+    #[wasm_bindgen]
+    pub fn get_aux_inserts(&self, aux: usize) -> js_sys::Array {
+        self.insert_names(Strip::Aux(aux))
+    }
+
+    #[wasm_bindgen]
+    pub fn get_master_inserts(&self) -> js_sys::Array {
+        self.insert_names(Strip::Master)
+    }
+
+    /// Sets `param` of the insert effect named `name`, e.g. the `ratio` of a compressor
+    #[wasm_bindgen]
+    pub fn set_effect_param(&self, name: &str, param: &str, value: f32) {
+        let bus = unsafe { get_bus() };
+        bus.trigger(format!("{}.{}", name, param), value);
+    }
+
+    #[wasm_bindgen]
+    pub fn get_effect_param(&self, name: &str, param: &str) -> f32 {
+        let bus = unsafe { get_bus() };
+        bus.value(format!("{}.{}", name, param))
+    }
+
+    /// How much (in dB) the compressor insert named `name` is currently turning its input down
+    #[wasm_bindgen]
+    pub fn get_compressor_reduction(&self, name: &str) -> Result<f32, JsValue> {
+        let mut strips = self.strips.borrow_mut();
+        let slot = strips.insert(name).ok_or_else(|| no_insert(name))?;
+        let compressor = slot.effect().as_any().downcast_ref::<Compressor>()
+            .ok_or_else(|| JsValue::from(format!("{} isn't a compressor", name)))?;
+        Ok(compressor.reduction())
+    }
+
+    /// Sets the impulse response the reverb insert named `name` convolves with in `Loaded` mode,
+    /// e.g. decoded from a file with `decodeAudioData`
+    #[wasm_bindgen]
+    pub fn set_reverb_impulse(&self, name: &str, buffer: AudioBuffer) -> Result<(), JsValue> {
+        let mut strips = self.strips.borrow_mut();
+        let slot = strips.insert(name).ok_or_else(|| no_insert(name))?;
+        as_reverb(slot, name)?.load_impulse(buffer)
+    }
+
+    /// The parameters of the reverb insert named `name`, as a `ReverbPatch`
+    #[wasm_bindgen]
+    pub fn save_reverb(&self, name: &str) -> Result<JsValue, JsValue> {
+        {
+            let mut strips = self.strips.borrow_mut();
+            let slot = strips.insert(name).ok_or_else(|| no_insert(name))?;
+            as_reverb(slot, name)?;
+        }
+        JsValue::from_serde(&ReverbPatch::read(name)).map_err(|e| e.to_string().into())
+    }
+
+    #[wasm_bindgen]
+    pub fn load_reverb(&self, name: &str, patch: &JsValue) -> Result<(), JsValue> {
+        let patch: ReverbPatch = patch.into_serde().map_err(|e| JsValue::from(e.to_string()))?;
+        {
+            let mut strips = self.strips.borrow_mut();
+            let slot = strips.insert(name).ok_or_else(|| no_insert(name))?;
+            as_reverb(slot, name)?;
+        }
+        patch.apply(name);
+        Ok(())
+    }
+
+    /// Sets how much (0 to 1) of the insert named `name` is heard over its dry input
+    #[wasm_bindgen]
+    pub fn set_insert_mix(&self, name: &str, mix: f32) {
+        let bus = unsafe { get_bus() };
+        bus.trigger(format!("{}.mix", name), mix);
+    }
+
+    #[wasm_bindgen]
+    pub fn get_insert_mix(&self, name: &str) -> f32 {
+        let bus = unsafe { get_bus() };
+        bus.value(format!("{}.mix", name))
+    }
+
+    #[wasm_bindgen]
+    pub fn set_insert_bypass(&self, name: &str, bypassed: bool) {
+        let bus = unsafe { get_bus() };
+        bus.trigger(format!("{}.bypass", name), if bypassed { 1.0 } else { 0.0 });
+    }
+
+    #[wasm_bindgen]
+    pub fn get_insert_bypass(&self, name: &str) -> bool {
+        let bus = unsafe { get_bus() };
+        bus.value(format!("{}.bypass", name)) > 0.5
+    }
+
+    /// Levels of every channel, aux return and the master, to be polled e.g. every frame:
+    /// `{channels: [level], auxes: [level], master: level}`, each level being
+    /// `{peak, rms, peak_hold, clip}` in dBFS, `clip` staying on until `reset_meters`.
+    #[wasm_bindgen]
+    pub fn meters(&self) -> Result<JsValue, JsValue> {
+        let now = self.ctx.current_time();
+        let strips = &mut *self.strips.borrow_mut();
+        let meters = Meters {
+            channels: strips.channels.iter_mut().map(|c| c.meter.measure(now)).collect(),
+            auxes: strips.auxes.iter_mut().map(|c| c.meter.measure(now)).collect(),
+            master: strips.master.meter.measure(now),
+        };
+        JsValue::from_serde(&meters).map_err(|e| e.to_string().into())
+    }
+
+    /// Clears the meters' clip indicators and peak holds
+    #[wasm_bindgen]
+    pub fn reset_meters(&self) {
+        for c in self.strips.borrow_mut().all_mut() {
+            c.meter.reset();
+        }
+    }
+
+    /// Checks the master output for clipping, ticks the inserts and applies pending
+    /// insert changes. Call it regularly, e.g. every frame.
+    #[wasm_bindgen]
+    pub fn tick(&mut self) -> Result<(), JsValue> {
+        self.limiter.check();
+        let now = self.ctx.current_time();
+        for c in self.strips.borrow_mut().all_mut() {
+            c.inserts.update(now)?;
+        }
+        Ok(())
+    }
+
+    /// How many times the master output has clipped, and had to be limited
+    #[wasm_bindgen]
+    pub fn get_clip_count(&self) -> u32 {
+        self.limiter.clips
+    }
+
+    #[wasm_bindgen]
+    pub fn reset_clip_count(&mut self) {
+        self.limiter.clips = 0;
+    }
+
+    /// How much the limiter is currently turning the master output down, in dB
+    #[wasm_bindgen]
+    pub fn get_limiter_reduction(&self) -> f32 {
+        self.limiter.reduction()
+    }
+
+    #[wasm_bindgen]
+    pub fn connect_to_speakers(&self) {
+        self.limiter.output().connect_with_audio_node(&self.ctx.destination()).unwrap();
+    }
+
+    fn channel_id(&self, idx: usize) -> Result<usize, JsValue> {
+        let strips = self.strips.borrow();
+        strips.channels.get(idx).map(|c| c.id)
+            .ok_or_else(|| format!("No mixer channel {}, there are {}", idx, strips.channels.len()).into())
+    }
+
+    fn set(&self, idx: usize, control: &str, value: f32) -> Result<(), JsValue> {
+        let bus = unsafe { get_bus() };
+        bus.trigger(format!("mixer.{}.{}", self.channel_id(idx)?, control), value);
+        Ok(())
+    }
+
+    fn get(&self, idx: usize, control: &str) -> Result<f32, JsValue> {
+        let bus = unsafe { get_bus() };
+        Ok(bus.value(format!("mixer.{}.{}", self.channel_id(idx)?, control)))
+    }
+
+    fn add_insert_to(&mut self, strip: Strip, kind: &str, name: String) -> Result<usize, JsValue> {
+        let bus = unsafe { get_bus() };
+        if self.strips.borrow_mut().get(strip).is_none() {
+            return Err(format!("No mixer strip {}", strip.id()).into());
+        }
+        if !bus.controls(&format!("{}.", name)).is_empty() {
+            return Err(format!("Name {} already taken", name).into());
+        }
+
+        let effect = create_effect(kind, name.clone(), self.ctx.clone())?;
+        let mix = effect.default_mix();
+        let slot = InsertSlot::new(self.ctx.clone(), name.clone(), effect)?;
+        let position = self.strips.borrow_mut().get(strip).unwrap().inserts.add(slot);
+
+        let s = self.strips.clone();
+        let n = name.clone();
+        bus.control(format!("{}.mix", name), mix, Box::new(move |v| {
+            if let Some(slot) = s.borrow_mut().insert(&n) {
+                slot.set_mix(v);
+            }
+        }));
+        let s = self.strips.clone();
+        let n = name.clone();
+        bus.control(format!("{}.bypass", name), 0.0, Box::new(move |v| {
+            if let Some(slot) = s.borrow_mut().insert(&n) {
+                slot.set_bypass(v > 0.5);
+            }
+        }));
+
+        Ok(position)
+    }
+
+    fn insert_names(&self, strip: Strip) -> js_sys::Array {
+        let names = js_sys::Array::new();
+        if let Some(c) = self.strips.borrow_mut().get(strip) {
+            for slot in &c.inserts.slots {
+                names.push(&JsValue::from(slot.name.as_str()));
+            }
+        }
+        names
+    }
+}
+
+fn no_insert(name: &str) -> JsValue {
+    format!("No insert named {}", name).into()
+}
+
+/// The reverb in `slot`, the insert named `name`
+fn as_reverb<'a>(slot: &'a InsertSlot, name: &str) -> Result<&'a Reverb, JsValue> {
+    slot.effect().as_any().downcast_ref::<Reverb>()
+        .ok_or_else(|| format!("{} isn't a reverb", name).into())
+}
+
+impl Drop for Mixer {
+    fn drop(&mut self) {
+        let bus = unsafe { get_bus() };
+        bus.remove("mixer.");
+        // Insert controls are named after their effects rather than the mixer
+        let names = self.strips.borrow().insert_names();
+        for name in names {
+            bus.remove(&format!("{}.", name));
+        }
+    }
+}
+
+impl AudioInputs for Mixer {
+    fn inputs(&self) -> Vec<AudioNode> {
+        self.strips.borrow().channels.iter().map(|x| x.input()).collect()
+    }
+}
+
+fn check_sub_type(waveform: OscillatorType) -> Result<(), JsValue> {
+    match waveform {
+        OscillatorType::Sine | OscillatorType::Square => Ok(()),
+        _ => Err("Sub oscillator can only be sine or square".into()),
+    }
+}
+
+fn check_sub_octave(octave: u8) -> Result<(), JsValue> {
+    if !(1..=2).contains(&octave) {
+        return Err("Sub oscillator can only be 1 or 2 octaves down".into());
+    }
+    Ok(())
+}
+
+#[wasm_bindgen]
+pub struct Subjam {
+    name: String,
+    osc1: Oscillator,
+    osc2: Oscillator,
+    noise: Noise,
+    noise_voices: Oscillator,
+    msegs: Vec<MsegPlayer>,
+    mod_matrix: ModMatrix,
+    notes_down: usize,
+    split: bool,
+    normalize: bool,
+    /// Output gain last set, which normalizing lowers
+    out_gain: f32,
+    pub osc_mix: f32,
+    pub filter_frequency: u32,
+    pub filter_q: f32,
+    ctx: AudioContext,
+    out: GainNode,
+}
+
+impl AudioOutput for Subjam {
+    fn output(&self) -> AudioNode {
+        self.out.clone().into()
+    }
+}
+
+#[wasm_bindgen]
+impl Subjam {
+    #[wasm_bindgen(constructor)]
+    pub fn new(ctx: AudioContext) -> Result<Subjam, JsValue> {
+        Subjam::with_name("subjam".to_string(), ctx)
+    }
+
+    /// Creates a Subjam whose bus controls are named after `name`, e.g. `<name>.osc_mix`.
+    ///
+    /// Fails if another instrument or effect already goes by `name`.
+    pub fn with_name(name: String, ctx: AudioContext) -> Result<Subjam, JsValue> {
+        instrument::check_name(&name)?;
+        let bus = unsafe { get_bus() };
+
+        let polyphony = 16;
+        let unison = 1;
+        let filter_frequency = FILTER_MAX_FREQ;
+        let filter_q = 0.0;
+        let mut osc1 = Oscillator::new(format!("{}.osc1", name), ctx.clone(), polyphony, unison, filter_frequency, filter_q)?;
+        let mut osc2 = Oscillator::new(format!("{}.osc2", name), ctx.clone(), polyphony, unison, filter_frequency, filter_q)?;
+        let noise = Noise::new(ctx.clone())?;
+        let noise_voices = Oscillator::with_source(format!("{}.noise", name), ctx.clone(), polyphony, &noise.output(), 0.0, filter_frequency, filter_q)?;
+
+        let gain = ctx.clone().create_gain()?;
+        gain.gain().set_value_at_time(OUT_GAIN, ctx.current_time())?;
+
+        osc1.set_waveform(OscillatorType::Sawtooth);
+        osc1.add_sub()?;
+        osc2.set_waveform(OscillatorType::Square);
+
+        let prefix = name.clone();
+        bus.control(format!("{}.osc_mix", name), 0.5, Box::new(move |v| {
+            let b = unsafe { get_bus() };
+            b.trigger(format!("{}.osc1.gain", prefix), 1.0 - v);
+            b.trigger(format!("{}.osc2.gain", prefix), v);
+        }));
+
+        // Per-voice destinations covering every voice bank
+        for dest in &["pitch", "cutoff", "resonance"] {
+            let prefix = name.clone();
+            bus.voice_control(format!("{}.{}", name, dest), Box::new(move |voice, v| {
+                let b = unsafe { get_bus() };
+                for bank in &["osc1", "osc2", "noise"] {
+                    b.trigger_voice(format!("{}.{}.{}", prefix, bank, dest), voice, v);
+                }
+            }));
+        }
+
+        osc1.on();
+        osc2.on();
+        noise.on();
+        noise_voices.on();
+
+        osc1.connect_with_audio_node(&gain)?;
+        osc2.connect_with_audio_node(&gain)?;
+        noise_voices.connect_with_audio_node(&gain)?;
+
+        let subjam = Subjam {
+            name,
+            osc_mix: 0.5,
+            osc1,
+            osc2,
+            noise,
+            noise_voices,
+            msegs: vec![],
+            mod_matrix: Default::default(),
+            notes_down: 0,
+            split: false,
+            normalize: false,
+            out_gain: OUT_GAIN,
+            filter_frequency,
+            filter_q,
+            ctx,
+            out: gain,
+        };
+
+        Ok(subjam)
+    }
+
+    #[wasm_bindgen]
+    pub fn set_osc1_type(&mut self, waveform: OscillatorType) {
+        self.osc1.set_waveform(waveform);
+    }
+
+    #[wasm_bindgen]
+    pub fn set_osc2_type(&mut self, waveform: OscillatorType) {
+        self.osc2.set_waveform(waveform);
+    }
+
+    #[wasm_bindgen]
+    pub fn get_osc1_type(&self) -> OscillatorType {
+        self.osc1.osc_type
+    }
+
+    #[wasm_bindgen]
+    pub fn get_osc2_type(&self) -> OscillatorType {
+        self.osc2.osc_type
+    }
+
+    /// Hard-syncs osc2 to osc1. Needs `load_worklets` to have resolved.
+    #[wasm_bindgen]
+    pub fn set_sync(&mut self, enabled: bool) -> Result<(), JsValue> {
+        self.osc2.set_sync(enabled)
+    }
+
+    #[wasm_bindgen]
+    pub fn get_sync(&self) -> bool {
+        self.osc2.sync
+    }
+
+    /// Sets osc2's pitch as a ratio of osc1's while synced.
+    #[wasm_bindgen]
+    pub fn set_sync_ratio(&mut self, ratio: f32) {
+        self.osc2.set_sync_ratio(ratio);
+    }
+
+    #[wasm_bindgen]
+    pub fn get_sync_ratio(&self) -> f32 {
+        self.osc2.sync_ratio
+    }
+
+    /// Sets the sub oscillator's shape, which can only be sine or square.
+    #[wasm_bindgen]
+    pub fn set_sub_type(&mut self, waveform: OscillatorType) -> Result<(), JsValue> {
+        check_sub_type(waveform)?;
+        self.osc1.set_sub_type(waveform);
+        Ok(())
+    }
+
+    #[wasm_bindgen]
+    pub fn get_sub_type(&self) -> OscillatorType {
+        self.osc1.sub_type
+    }
+
+    /// Sets how many octaves (1 or 2) below osc1 the sub oscillator plays.
+    #[wasm_bindgen]
+    pub fn set_sub_octave(&mut self, octave: u8) -> Result<(), JsValue> {
+        check_sub_octave(octave)?;
+        self.osc1.set_sub_octave(octave);
+        Ok(())
+    }
+
+    #[wasm_bindgen]
+    pub fn get_sub_octave(&self) -> u8 {
+        self.osc1.sub_octave
+    }
+
+    #[wasm_bindgen]
+    pub fn set_sub_level(&mut self, level: f32) {
+        self.osc1.set_sub_level(level);
+    }
+
+    #[wasm_bindgen]
+    pub fn get_sub_level(&self) -> f32 {
+        self.osc1.sub_level
+    }
+
+    #[wasm_bindgen]
+    pub fn set_noise_color(&mut self, color: NoiseColor) {
+        self.noise.set_color(color);
+    }
+
+    #[wasm_bindgen]
+    pub fn get_noise_color(&self) -> NoiseColor {
+        self.noise.color
+    }
+
+    /// The noise level lives on the bus as `<name>.noise.gain`.
+    #[wasm_bindgen]
+    pub fn get_noise_level(&self) -> f32 {
+        let bus = unsafe { get_bus() };
+        bus.value(self.control("noise.gain"))
+    }
+
+    #[wasm_bindgen]
+    pub fn set_filter_frequency(&mut self, f: u32) {
+        self.filter_frequency = f;
+        for o in self.linked(1) {
+            o.set_filter_frequency(f);
+        }
+    }
+
+    /// Switches the voices' filter type, adapting how resonance is applied to it.
+    #[wasm_bindgen]
+    pub fn set_filter_type(&mut self, filter_type: BiquadFilterType) {
+        for o in self.linked(1) {
+            o.set_filter_type(filter_type);
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn get_filter_type(&self) -> BiquadFilterType {
+        self.osc1.filter_type
+    }
+
+    #[wasm_bindgen]
+    pub fn set_filter_resonance(&mut self, q: f32) {
+        self.filter_q = q;
+        for o in self.linked(1) {
+            o.set_filter_resonance(q);
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn get_filter_frequency(&self) -> u32 {
+        self.filter_frequency
+    }
+
+    #[wasm_bindgen]
+    pub fn get_filter_resonance(&self) -> f32 {
+        self.filter_q
+    }
+
+    #[wasm_bindgen]
+    pub fn set_amp_attack(&mut self, v: f32) {
+        for o in self.linked(1) {
+            o.set_amp_attack(v);
+        }
+    }
+    #[wasm_bindgen]
+    pub fn set_amp_decay(&mut self, v: f32) {
+        for o in self.linked(1) {
+            o.set_amp_decay(v);
+        }
+    }
+    #[wasm_bindgen]
+    pub fn set_amp_sustain(&mut self, v: f32) {
+        for o in self.linked(1) {
+            o.set_amp_sustain(v);
+        }
+    }
+    #[wasm_bindgen]
+    pub fn set_amp_release(&mut self, v: f32) {
+        for o in self.linked(1) {
+            o.set_amp_release(v);
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn set_filter_attack(&mut self, v: f32) {
+        for o in self.linked(1) {
+            o.set_filter_attack(v);
+        }
+    }
+    #[wasm_bindgen]
+    pub fn set_filter_decay(&mut self, v: f32) {
+        for o in self.linked(1) {
+            o.set_filter_decay(v);
+        }
+    }
+    #[wasm_bindgen]
+    pub fn set_filter_sustain(&mut self, v: f32) {
+        for o in self.linked(1) {
+            o.set_filter_sustain(v);
+        }
+    }
+    #[wasm_bindgen]
+    pub fn set_filter_release(&mut self, v: f32) {
+        for o in self.linked(1) {
+            o.set_filter_release(v);
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn set_amp_delay(&mut self, v: f32) {
+        for o in self.linked(1) {
+            o.set_amp_delay(v);
+        }
+    }
+    #[wasm_bindgen]
+    pub fn set_amp_hold(&mut self, v: f32) {
+        for o in self.linked(1) {
+            o.set_amp_hold(v);
+        }
+    }
+    #[wasm_bindgen]
+    pub fn set_filter_delay(&mut self, v: f32) {
+        for o in self.linked(1) {
+            o.set_filter_delay(v);
+        }
+    }
+    #[wasm_bindgen]
+    pub fn set_filter_hold(&mut self, v: f32) {
+        for o in self.linked(1) {
+            o.set_filter_hold(v);
+        }
+    }
+
+    /// Sets the shape of one stage of the amp envelope, `tension` (0 to 1) being how curved it is.
+    #[wasm_bindgen]
+    pub fn set_amp_curve(&mut self, stage: EnvelopeStage, shape: CurveShape, tension: f32) {
+        for o in self.linked(1) {
+            o.set_amp_curve(stage, shape, tension);
+        }
+    }
+    #[wasm_bindgen]
+    pub fn set_filter_curve(&mut self, stage: EnvelopeStage, shape: CurveShape, tension: f32) {
+        for o in self.linked(1) {
+            o.set_filter_curve(stage, shape, tension);
+        }
+    }
+
+    /// Sets how far, in cents, the filter envelope sweeps from the cutoff. Negative values sweep down.
+    #[wasm_bindgen]
+    pub fn set_filter_env_amount(&mut self, cents: f32) {
+        self.osc1.set_filter_env_amount(cents);
+        self.osc2.set_filter_env_amount(cents);
+        self.noise_voices.set_filter_env_amount(cents);
+    }
+
+    #[wasm_bindgen]
+    pub fn get_filter_env_amount(&self) -> f32 {
+        self.osc1.filter_env_amount
+    }
+
+    /// Sets how much (0 to 1) the cutoff follows the notes played, around the key center.
+    #[wasm_bindgen]
+    pub fn set_key_tracking(&mut self, amount: f32) {
+        self.osc1.set_key_tracking(amount);
+        self.osc2.set_key_tracking(amount);
+        self.noise_voices.set_key_tracking(amount);
+    }
+
+    #[wasm_bindgen]
+    pub fn get_key_tracking(&self) -> f32 {
+        self.osc1.key_tracking
+    }
+
+    #[wasm_bindgen]
+    pub fn set_key_center(&mut self, note: u8) {
+        self.osc1.set_key_center(note);
+        self.osc2.set_key_center(note);
+        self.noise_voices.set_key_center(note);
+    }
+
+    #[wasm_bindgen]
+    pub fn get_key_center(&self) -> u8 {
+        self.osc1.key_center
+    }
+
+    /// Sets how far, in cents, a full velocity note moves the cutoff.
+    #[wasm_bindgen]
+    pub fn set_velocity_cutoff(&mut self, cents: f32) {
+        self.osc1.set_velocity_cutoff(cents);
+        self.osc2.set_velocity_cutoff(cents);
+        self.noise_voices.set_velocity_cutoff(cents);
+    }
+
+    #[wasm_bindgen]
+    pub fn get_velocity_cutoff(&self) -> f32 {
+        self.osc1.velocity_cutoff
+    }
+
+    #[wasm_bindgen]
+    pub fn set_velocity_curve(&mut self, curve: VelocityCurve) {
+        self.edit_velocity(|v| v.curve = curve);
+    }
+
+    #[wasm_bindgen]
+    pub fn get_velocity_curve(&self) -> VelocityCurve {
+        self.osc1.velocity.curve
+    }
+
+    /// Sets the levels (0 to 1) of `VelocityCurve::Custom`, for velocities evenly spread from 0 to 127.
+    #[wasm_bindgen]
+    pub fn set_velocity_table(&mut self, table: Vec<f32>) {
+        self.edit_velocity(|v| v.table = table.clone());
+    }
+
+    #[wasm_bindgen]
+    pub fn get_velocity_table(&self) -> Vec<f32> {
+        self.osc1.velocity.table.clone()
+    }
+
+    /// Sets the level (0 to 1) every note plays at with `VelocityCurve::Fixed`.
+    #[wasm_bindgen]
+    pub fn set_velocity_fixed(&mut self, level: f32) {
+        self.edit_velocity(|v| v.fixed = level);
+    }
+
+    #[wasm_bindgen]
+    pub fn get_velocity_fixed(&self) -> f32 {
+        self.osc1.velocity.fixed
+    }
+
+    /// Sets how much (0 to 1) velocity scales the notes' gain.
+    #[wasm_bindgen]
+    pub fn set_velocity_amp(&mut self, amount: f32) {
+        self.edit_velocity(|v| v.amp = amount);
+    }
+
+    #[wasm_bindgen]
+    pub fn get_velocity_amp(&self) -> f32 {
+        self.osc1.velocity.amp
+    }
+
+    /// Sets how much (-1 to 1) velocity shortens the envelopes' attack and decay.
     #[wasm_bindgen]
-    pub fn set_gain(&self, idx: usize, gain: f32) {
-        assert!(self.channels.len() > idx, "Not enough channels");
-        self.channels[idx].set_gain(gain);
+    pub fn set_velocity_env_time(&mut self, amount: f32) {
+        self.edit_velocity(|v| v.env_time = amount);
     }
 
     #[wasm_bindgen]
-    pub fn connect_to_speakers(&self) {
-        self.master.output().connect_with_audio_node(&self.ctx.destination()).unwrap();
+    pub fn get_velocity_env_time(&self) -> f32 {
+        self.osc1.velocity.env_time
     }
-}
 
-impl AudioInputs for Mixer {
-    fn inputs(&self) -> Vec<AudioNode> {
-        self.channels.iter().map(|x| x.input()).collect()
+    /// Sets how much (0 to 1) velocity scales osc2's sync ratio, i.e. how bright hard sync gets
+    #[wasm_bindgen]
+    pub fn set_velocity_fm(&mut self, amount: f32) {
+        self.edit_velocity(|v| v.fm = amount);
     }
-}
 
-#[wasm_bindgen]
-pub struct Subjam {
-    osc1: Oscillator,
-    osc2: Oscillator,
-    pub osc_mix: f32,
-    pub filter_frequency: u32,
-    pub filter_q: f32,
-    out: GainNode,
-}
+    #[wasm_bindgen]
+    pub fn get_velocity_fm(&self) -> f32 {
+        self.osc1.velocity.fm
+    }
 
-impl AudioOutput for Subjam {
-    fn output(&self) -> AudioNode {
-        self.out.clone().into()
+    #[wasm_bindgen]
+    pub fn get_osc1_amp_env(&self) -> Envelope {
+        self.osc1.amp_env.clone()
     }
-}
 
-#[wasm_bindgen]
-impl Subjam {
-    #[wasm_bindgen(constructor)]
-    pub fn new(ctx: AudioContext) -> Result<Subjam, JsValue> {
-        let bus = unsafe { get_bus() };
+    #[wasm_bindgen]
+    pub fn get_osc2_amp_env(&self) -> Envelope {
+        self.osc2.amp_env.clone()
+    }
 
-        let polyphony = 16;
-        let unison = 1;
-        let filter_frequency = FILTER_MAX_FREQ;
-        let filter_q = 0.0;
-        let mut osc1 = Oscillator::new("subjam.osc1".to_string(), ctx.clone(), polyphony, unison, filter_frequency, filter_q)?;
-        let mut osc2 = Oscillator::new("subjam.osc2".to_string(), ctx.clone(), polyphony, unison, filter_frequency, filter_q)?;
+    #[wasm_bindgen]
+    pub fn get_osc1_filter_env(&self) -> Envelope {
+        self.osc1.filter_env.clone()
+    }
 
-        let gain = ctx.clone().create_gain()?;
-        gain.gain().set_value_at_time(0.5, ctx.current_time())?;
+    #[wasm_bindgen]
+    pub fn get_osc2_filter_env(&self) -> Envelope {
+        self.osc2.filter_env.clone()
+    }
 
-        osc1.set_waveform(OscillatorType::Sawtooth);
-        osc2.set_waveform(OscillatorType::Square);
+    /// Gives each oscillator its own envelopes and filter, edited with the `set_osc1_*` and
+    /// `set_osc2_*` setters. The noise follows osc1, and so do the envelope and filter setters
+    /// edited without one. Unsplitting copies osc1's settings onto osc2.
+    #[wasm_bindgen]
+    pub fn set_split(&mut self, enabled: bool) {
+        self.split = enabled;
+        if !enabled {
+            let amp_env = self.osc1.amp_env.clone();
+            let filter_env = self.osc1.filter_env.clone();
+            self.set_osc1_amp_env(&amp_env);
+            self.set_osc1_filter_env(&filter_env);
+            self.set_osc1_filter_type(self.osc1.filter_type);
+            self.set_osc1_filter_frequency(self.osc1.filter_frequency);
+            self.set_osc1_filter_resonance(self.osc1.filter_resonance);
+        }
+    }
 
-        bus.control("subjam.osc_mix".to_string(), 0.5, Box::new(|v| {
-            let b = unsafe { get_bus() };
-            b.trigger("subjam.osc1.gain".to_string(), 1.0 - v);
-            b.trigger("subjam.osc2.gain".to_string(), v);
-        }));
+    #[wasm_bindgen]
+    pub fn get_split(&self) -> bool {
+        self.split
+    }
 
-        osc1.on();
-        osc2.on();
+    #[wasm_bindgen]
+    pub fn set_osc1_amp_env(&mut self, env: &Envelope) {
+        self.set_osc_amp_env(1, env);
+    }
 
-        osc1.connect_with_audio_node(&gain)?;
-        osc2.connect_with_audio_node(&gain)?;
+    #[wasm_bindgen]
+    pub fn set_osc2_amp_env(&mut self, env: &Envelope) {
+        self.set_osc_amp_env(2, env);
+    }
 
-        let subjam = Subjam {
-            osc_mix: 0.5,
-            osc1,
-            osc2,
-            filter_frequency,
-            filter_q,
-            out: gain,
-        };
+    #[wasm_bindgen]
+    pub fn set_osc1_filter_env(&mut self, env: &Envelope) {
+        self.set_osc_filter_env(1, env);
+    }
 
-        Ok(subjam)
+    #[wasm_bindgen]
+    pub fn set_osc2_filter_env(&mut self, env: &Envelope) {
+        self.set_osc_filter_env(2, env);
     }
 
     #[wasm_bindgen]
-    pub fn set_osc1_type(&mut self, waveform: OscillatorType) {
-        self.osc1.set_waveform(waveform);
+    pub fn set_osc1_filter_type(&mut self, filter_type: BiquadFilterType) {
+        self.set_osc_filter_type(1, filter_type);
     }
 
     #[wasm_bindgen]
-    pub fn set_osc2_type(&mut self, waveform: OscillatorType) {
-        self.osc2.set_waveform(waveform);
+    pub fn set_osc2_filter_type(&mut self, filter_type: BiquadFilterType) {
+        self.set_osc_filter_type(2, filter_type);
     }
 
     #[wasm_bindgen]
-    pub fn get_osc1_type(&self) -> OscillatorType {
-        self.osc1.osc_type
+    pub fn get_osc1_filter_type(&self) -> BiquadFilterType {
+        self.osc1.filter_type
     }
 
     #[wasm_bindgen]
-    pub fn get_osc2_type(&self) -> OscillatorType {
-        self.osc2.osc_type
+    pub fn get_osc2_filter_type(&self) -> BiquadFilterType {
+        self.osc2.filter_type
     }
 
     #[wasm_bindgen]
-    pub fn set_filter_frequency(&mut self, f: u32) {
-        self.filter_frequency = f;
-        self.osc1.set_filter_frequency(f);
-        self.osc2.set_filter_frequency(f);
+    pub fn set_osc1_filter_frequency(&mut self, f: u32) {
+        self.set_osc_filter_frequency(1, f);
     }
 
     #[wasm_bindgen]
-    pub fn set_filter_resonance(&mut self, q: f32) {
-        self.filter_q = q;
-        self.osc1.set_filter_resonance(q);
-        self.osc2.set_filter_resonance(q);
+    pub fn set_osc2_filter_frequency(&mut self, f: u32) {
+        self.set_osc_filter_frequency(2, f);
     }
 
     #[wasm_bindgen]
-    pub fn get_filter_frequency(&self) -> u32 {
-        self.filter_frequency
+    pub fn get_osc1_filter_frequency(&self) -> u32 {
+        self.osc1.filter_frequency
     }
 
     #[wasm_bindgen]
-    pub fn get_filter_resonance(&self) -> f32 {
-        self.filter_q
+    pub fn get_osc2_filter_frequency(&self) -> u32 {
+        self.osc2.filter_frequency
     }
 
     #[wasm_bindgen]
-    pub fn set_amp_attack(&mut self, v: u32) {
-        self.osc1.set_amp_attack(v);
-        self.osc2.set_amp_attack(v);
+    pub fn set_osc1_filter_resonance(&mut self, q: f32) {
+        self.set_osc_filter_resonance(1, q);
     }
+
     #[wasm_bindgen]
-    pub fn set_amp_decay(&mut self, v: u32) {
-        self.osc1.set_amp_decay(v);
-        self.osc2.set_amp_decay(v);
+    pub fn set_osc2_filter_resonance(&mut self, q: f32) {
+        self.set_osc_filter_resonance(2, q);
     }
+
     #[wasm_bindgen]
-    pub fn set_amp_sustain(&mut self, v: f32) {
-        self.osc1.set_amp_sustain(v);
-        self.osc2.set_amp_sustain(v);
+    pub fn get_osc1_filter_resonance(&self) -> f32 {
+        self.osc1.filter_resonance
+    }
+
+    #[wasm_bindgen]
+    pub fn get_osc2_filter_resonance(&self) -> f32 {
+        self.osc2.filter_resonance
     }
+
     #[wasm_bindgen]
-    pub fn set_amp_release(&mut self, v: u32) {
-        self.osc1.set_amp_release(v);
-        self.osc2.set_amp_release(v);
+    pub fn set_voice_mode(&mut self, mode: VoiceMode) {
+        self.osc1.set_voice_mode(mode);
+        self.osc2.set_voice_mode(mode);
+        self.noise_voices.set_voice_mode(mode);
     }
 
     #[wasm_bindgen]
-    pub fn set_filter_attack(&mut self, v: u32) {
-        self.osc1.set_filter_attack(v);
-        self.osc2.set_filter_attack(v);
+    pub fn get_voice_mode(&self) -> VoiceMode {
+        self.osc1.voice_mode
     }
+
     #[wasm_bindgen]
-    pub fn set_filter_decay(&mut self, v: u32) {
-        self.osc1.set_filter_decay(v);
-        self.osc2.set_filter_decay(v);
+    pub fn set_note_priority(&mut self, priority: NotePriority) {
+        self.osc1.set_note_priority(priority);
+        self.osc2.set_note_priority(priority);
+        self.noise_voices.set_note_priority(priority);
     }
+
     #[wasm_bindgen]
-    pub fn set_filter_sustain(&mut self, v: f32) {
-        self.osc1.set_filter_sustain(v);
-        self.osc2.set_filter_sustain(v);
+    pub fn get_note_priority(&self) -> NotePriority {
+        self.osc1.note_priority
     }
+
+    /// Sets the glide time in milliseconds, 0 turns glide off
     #[wasm_bindgen]
-    pub fn set_filter_release(&mut self, v: u32) {
-        self.osc1.set_filter_release(v);
-        self.osc2.set_filter_release(v);
+    pub fn set_glide_time(&mut self, ms: u32) {
+        self.osc1.set_glide_time(ms);
+        self.osc2.set_glide_time(ms);
+        self.noise_voices.set_glide_time(ms);
     }
 
     #[wasm_bindgen]
-    pub fn get_osc1_amp_env(&self) -> Envelope {
-        self.osc1.amp_env.clone()
+    pub fn get_glide_time(&self) -> u32 {
+        self.osc1.glide_time
     }
 
     #[wasm_bindgen]
-    pub fn get_osc2_amp_env(&self) -> Envelope {
-        self.osc2.amp_env.clone()
+    pub fn set_glide_mode(&mut self, mode: GlideMode) {
+        self.osc1.set_glide_mode(mode);
+        self.osc2.set_glide_mode(mode);
+        self.noise_voices.set_glide_mode(mode);
     }
 
     #[wasm_bindgen]
-    pub fn get_osc1_filter_env(&self) -> Envelope {
-        self.osc1.filter_env.clone()
+    pub fn get_glide_mode(&self) -> GlideMode {
+        self.osc1.glide_mode
+    }
+
+    #[wasm_bindgen]
+    pub fn save_patch(&self) -> Result<JsValue, JsValue> {
+        let bus = unsafe { get_bus() };
+        let patch = Patch {
+            osc1_type: patch::name(self.osc1.osc_type),
+            osc2_type: patch::name(self.osc2.osc_type),
+            osc_mix: bus.value(self.control("osc_mix")),
+            sync: self.osc2.sync,
+            sync_ratio: self.osc2.sync_ratio,
+            sub_type: patch::name(self.osc1.sub_type),
+            sub_octave: self.osc1.sub_octave,
+            sub_level: self.osc1.sub_level,
+            noise_color: self.noise.color,
+            noise_level: bus.value(self.control("noise.gain")),
+            voice_mode: self.osc1.voice_mode,
+            note_priority: self.osc1.note_priority,
+            glide_mode: self.osc1.glide_mode,
+            glide_time: self.osc1.glide_time,
+            filter_type: patch::name(self.osc1.filter_type),
+            filter_frequency: self.filter_frequency,
+            filter_resonance: self.filter_q,
+            filter_env_amount: self.osc1.filter_env_amount,
+            key_tracking: self.osc1.key_tracking,
+            key_center: self.osc1.key_center,
+            velocity_cutoff: self.osc1.velocity_cutoff,
+            velocity: self.osc1.velocity.clone(),
+            mod_matrix: self.mod_matrix.clone(),
+            normalize: self.normalize,
+            amp_env: self.osc1.amp_env.clone(),
+            filter_env: self.osc1.filter_env.clone(),
+            split: if self.split {
+                Some(SplitPatch {
+                    amp_env: self.osc2.amp_env.clone(),
+                    filter_env: self.osc2.filter_env.clone(),
+                    filter_type: patch::name(self.osc2.filter_type),
+                    filter_frequency: self.osc2.filter_frequency,
+                    filter_resonance: self.osc2.filter_resonance,
+                })
+            } else {
+                None
+            },
+            msegs: self.msegs.iter().filter_map(|m| {
+                let (min, max) = m.range();
+                m.control().map(|target| MsegPatch { target: target.to_string(), min, max, mseg: m.mseg().clone() })
+            }).collect(),
+        };
+        JsValue::from_serde(&patch).map_err(|e| e.to_string().into())
+    }
+
+    #[wasm_bindgen]
+    pub fn load_patch(&mut self, patch: &JsValue) -> Result<(), JsValue> {
+        let bus = unsafe { get_bus() };
+        let patch: Patch = patch.into_serde().map_err(|e| JsValue::from(e.to_string()))?;
+
+        // Convert and check everything before changing anything, so a bad patch leaves the current one as it is
+        let osc1_type = patch::oscillator_type(&patch.osc1_type)?;
+        let osc2_type = patch::oscillator_type(&patch.osc2_type)?;
+        let sub_type = patch::oscillator_type(&patch.sub_type)?;
+        check_sub_type(sub_type)?;
+        check_sub_octave(patch.sub_octave)?;
+        let filter_type = patch::filter_type(&patch.filter_type)?;
+        let split_filter_type = match &patch.split {
+            Some(split) => Some(patch::filter_type(&split.filter_type)?),
+            None => None,
+        };
+        let msegs = patch.msegs.iter().map(|p| {
+            let mut m = MsegPlayer::for_control(p.target.clone(), p.min, p.max);
+            m.set(p.mseg.clone())?;
+            Ok(m)
+        }).collect::<Result<Vec<_>, JsValue>>()?;
+        if patch.sync {
+            // Syncing needs the worklets loaded
+            self.osc2.prepare_sync()?;
+        }
+
+        self.set_split(false);
+
+        self.set_osc1_type(osc1_type);
+        self.set_osc2_type(osc2_type);
+        self.osc_mix = patch.osc_mix;
+        bus.trigger(self.control("osc_mix"), patch.osc_mix);
+        self.set_sync_ratio(patch.sync_ratio);
+        self.set_sync(patch.sync)?;
+        self.set_sub_type(sub_type)?;
+        self.set_sub_octave(patch.sub_octave)?;
+        self.set_sub_level(patch.sub_level);
+        self.set_noise_color(patch.noise_color);
+        bus.trigger(self.control("noise.gain"), patch.noise_level);
+        self.set_voice_mode(patch.voice_mode);
+        self.set_note_priority(patch.note_priority);
+        self.set_glide_mode(patch.glide_mode);
+        self.set_glide_time(patch.glide_time);
+        self.set_filter_type(filter_type);
+        self.set_filter_frequency(patch.filter_frequency);
+        self.set_filter_resonance(patch.filter_resonance);
+        self.set_filter_env_amount(patch.filter_env_amount);
+        self.set_key_tracking(patch.key_tracking);
+        self.set_key_center(patch.key_center);
+        self.set_velocity_cutoff(patch.velocity_cutoff);
+        self.edit_velocity(|v| *v = patch.velocity.clone());
+
+        self.osc1.amp_env = patch.amp_env.clone();
+        self.osc2.amp_env = patch.amp_env.clone();
+        self.noise_voices.amp_env = patch.amp_env;
+        self.osc1.filter_env = patch.filter_env.clone();
+        self.osc2.filter_env = patch.filter_env.clone();
+        self.noise_voices.filter_env = patch.filter_env;
+        if let (Some(split), Some(split_filter_type)) = (patch.split, split_filter_type) {
+            self.set_split(true);
+            self.set_osc2_amp_env(&split.amp_env);
+            self.set_osc2_filter_env(&split.filter_env);
+            self.set_osc2_filter_type(split_filter_type);
+            self.set_osc2_filter_frequency(split.filter_frequency);
+            self.set_osc2_filter_resonance(split.filter_resonance);
+        }
+        self.set_normalize(patch.normalize);
+        self.mod_matrix.slots = patch.mod_matrix.slots;
+        self.mod_matrix.lfos = patch.mod_matrix.lfos;
+        self.msegs = msegs;
+        Ok(())
+    }
+
+    /// Adds an MSEG driving the bus control `target` between `min` and `max`, returning its index.
+    #[wasm_bindgen]
+    pub fn add_mseg(&mut self, target: String, min: f32, max: f32) -> usize {
+        self.msegs.push(MsegPlayer::for_control(target, min, max));
+        self.msegs.len() - 1
+    }
+
+    #[wasm_bindgen]
+    pub fn remove_mseg(&mut self, idx: usize) -> Result<(), JsValue> {
+        self.mseg_at(idx)?;
+        self.msegs.remove(idx);
+        Ok(())
+    }
+
+    /// Sets an MSEG's breakpoints, sustain and loop points (see `MsegPlayer::set_mseg`).
+    #[wasm_bindgen]
+    pub fn set_mseg(&mut self, idx: usize, mseg: &JsValue) -> Result<(), JsValue> {
+        self.mseg_at(idx)?.set_mseg(mseg)
+    }
+
+    #[wasm_bindgen]
+    pub fn get_mseg(&mut self, idx: usize) -> Result<JsValue, JsValue> {
+        self.mseg_at(idx)?.get_mseg()
+    }
+
+    /// Pushes the MSEGs' and the mod matrix's current values to their controls, and follows
+    /// released voices dying out when normalizing. Call it regularly, e.g. every frame.
+    #[wasm_bindgen]
+    pub fn tick(&mut self) {
+        let now = self.ctx.current_time();
+        for m in &mut self.msegs {
+            m.tick(now);
+        }
+        let elapsed = self.mod_matrix.advance(now);
+        self.osc1.smooth_aftertouch(elapsed);
+        self.osc2.smooth_aftertouch(elapsed);
+        self.noise_voices.smooth_aftertouch(elapsed);
+        self.modulate();
+        // Released voices going silent turn the output back up
+        self.update_gain();
+    }
+
+    /// Adds a mod matrix slot from `{source, via, destination, amount}`, returning its index.
+    ///
+    /// Sources and via are `ModSource` names, e.g. `"Lfo1"`, and via can be null. The
+    /// destination is any bus control, e.g. `"subjam.osc_mix"`. `"<name>.pitch"`,
+    /// `"<name>.cutoff"` (both in cents) and `"<name>.resonance"` are modulated per voice.
+    #[wasm_bindgen]
+    pub fn add_mod_slot(&mut self, slot: &JsValue) -> Result<usize, JsValue> {
+        let slot: ModSlot = slot.into_serde().map_err(|e| JsValue::from(e.to_string()))?;
+        self.mod_matrix.slots.push(slot);
+        Ok(self.mod_matrix.slots.len() - 1)
+    }
+
+    #[wasm_bindgen]
+    pub fn set_mod_slot(&mut self, idx: usize, slot: &JsValue) -> Result<(), JsValue> {
+        *self.mod_slot_at(idx)? = slot.into_serde().map_err(|e| JsValue::from(e.to_string()))?;
+        Ok(())
+    }
+
+    #[wasm_bindgen]
+    pub fn get_mod_slot(&mut self, idx: usize) -> Result<JsValue, JsValue> {
+        JsValue::from_serde(self.mod_slot_at(idx)?).map_err(|e| e.to_string().into())
+    }
+
+    #[wasm_bindgen]
+    pub fn remove_mod_slot(&mut self, idx: usize) -> Result<(), JsValue> {
+        self.mod_slot_at(idx)?;
+        self.mod_matrix.slots.remove(idx);
+        Ok(())
+    }
+
+    #[wasm_bindgen]
+    pub fn mod_slot_count(&self) -> usize {
+        self.mod_matrix.slots.len()
+    }
+
+    #[wasm_bindgen]
+    pub fn set_lfo_rate(&mut self, idx: usize, hz: f32) -> Result<(), JsValue> {
+        self.lfo_at(idx)?.rate = hz;
+        Ok(())
+    }
+
+    #[wasm_bindgen]
+    pub fn get_lfo_rate(&mut self, idx: usize) -> Result<f32, JsValue> {
+        Ok(self.lfo_at(idx)?.rate)
+    }
+
+    #[wasm_bindgen]
+    pub fn set_lfo_shape(&mut self, idx: usize, shape: LfoShape) -> Result<(), JsValue> {
+        self.lfo_at(idx)?.shape = shape;
+        Ok(())
+    }
+
+    #[wasm_bindgen]
+    pub fn get_lfo_shape(&mut self, idx: usize) -> Result<LfoShape, JsValue> {
+        Ok(self.lfo_at(idx)?.shape)
+    }
+
+    /// Sets the mod wheel position (0 to 127)
+    #[wasm_bindgen]
+    pub fn mod_wheel(&mut self, value: u8) {
+        self.mod_matrix.mod_wheel = value.min(127) as f32 / 127.0;
+    }
+
+    /// Sets the channel pressure (0 to 127) of every voice, for the `Aftertouch` mod source
+    #[wasm_bindgen]
+    pub fn aftertouch(&mut self, value: u8) {
+        let value = value.min(127) as f32 / 127.0;
+        self.osc1.aftertouch(value);
+        self.osc2.aftertouch(value);
+        self.noise_voices.aftertouch(value);
+    }
+
+    /// Sets the pressure (0 to 127) of the voice playing `note`, for the `Aftertouch` mod source
+    #[wasm_bindgen]
+    pub fn poly_aftertouch(&mut self, note: u8, value: u8) {
+        let value = value.min(127) as f32 / 127.0;
+        self.osc1.poly_aftertouch(note, value);
+        self.osc2.poly_aftertouch(note, value);
+        self.noise_voices.poly_aftertouch(note, value);
     }
 
     #[wasm_bindgen]
     pub fn note_on(&mut self, note: u8, velocity: u8) {
         self.osc1.note_on(note, velocity);
         self.osc2.note_on(note, velocity);
+        self.noise_voices.note_on(note, velocity);
+
+        let now = self.ctx.current_time();
+        self.notes_down += 1;
+        for m in &mut self.msegs {
+            m.start(now);
+        }
+        self.update_gain();
     }
 
     #[wasm_bindgen]
     pub fn note_off(&mut self, note: u8) {
         self.osc1.note_off(note);
         self.osc2.note_off(note);
+        self.noise_voices.note_off(note);
+
+        let now = self.ctx.current_time();
+        self.notes_down = self.notes_down.saturating_sub(1);
+        if self.notes_down == 0 {
+            for m in &mut self.msegs {
+                m.release(now);
+            }
+        }
+        self.update_gain();
+    }
+
+    /// Turns the output down as more voices sound together, keeping chords from clipping.
+    #[wasm_bindgen]
+    pub fn set_normalize(&mut self, enabled: bool) {
+        self.normalize = enabled;
+        self.update_gain();
+    }
+
+    #[wasm_bindgen]
+    pub fn get_normalize(&self) -> bool {
+        self.normalize
+    }
+
+    /// Scales the output by 1/sqrt of the voices sounding when normalizing, their sum being mostly uncorrelated.
+    ///
+    /// Voices count until they're done releasing, so tails don't jump up in level.
+    fn update_gain(&mut self) {
+        let voices = if self.normalize { self.osc1.sounding().max(1) } else { 1 };
+        let gain = OUT_GAIN / (voices as f32).sqrt();
+        if gain != self.out_gain {
+            self.out_gain = gain;
+            smooth::set(&self.out.gain(), gain, self.ctx.current_time(), smooth::GAIN);
+        }
+    }
+
+    fn edit_velocity<F: Fn(&mut Velocity)>(&mut self, edit: F) {
+        edit(&mut self.osc1.velocity);
+        edit(&mut self.osc2.velocity);
+        edit(&mut self.noise_voices.velocity);
+    }
+
+    /// The voice banks following osc1's or osc2's envelopes and filter
+    fn linked(&mut self, osc: u8) -> Vec<&mut Oscillator> {
+        match (self.split, osc) {
+            (false, _) => vec![&mut self.osc1, &mut self.osc2, &mut self.noise_voices],
+            (true, 2) => vec![&mut self.osc2],
+            (true, _) => vec![&mut self.osc1, &mut self.noise_voices],
+        }
+    }
+
+    fn set_osc_amp_env(&mut self, osc: u8, env: &Envelope) {
+        for o in self.linked(osc) {
+            o.amp_env = env.clone();
+        }
+    }
+
+    fn set_osc_filter_env(&mut self, osc: u8, env: &Envelope) {
+        for o in self.linked(osc) {
+            o.filter_env = env.clone();
+        }
+    }
+
+    fn set_osc_filter_type(&mut self, osc: u8, filter_type: BiquadFilterType) {
+        for o in self.linked(osc) {
+            o.set_filter_type(filter_type);
+        }
+    }
+
+    fn set_osc_filter_frequency(&mut self, osc: u8, f: u32) {
+        for o in self.linked(osc) {
+            o.set_filter_frequency(f);
+        }
+        self.filter_frequency = self.osc1.filter_frequency;
+    }
+
+    fn set_osc_filter_resonance(&mut self, osc: u8, q: f32) {
+        for o in self.linked(osc) {
+            o.set_filter_resonance(q);
+        }
+        self.filter_q = self.osc1.filter_resonance;
+    }
+
+    /// Applies the mod matrix to its destinations
+    fn modulate(&mut self) {
+        let bus = unsafe { get_bus() };
+        let last = self.osc1.voice_sources(self.osc1.last_voice());
+        for dest in self.mod_matrix.destinations() {
+            if bus.is_voice_control(&dest) {
+                for idx in 0..self.osc1.polyphony {
+                    let value = self.mod_matrix.value(&dest, &self.osc1.voice_sources(idx));
+                    bus.trigger_voice(dest.clone(), idx, value);
+                }
+            } else {
+                bus.offset(dest.clone(), self.mod_matrix.value(&dest, &last));
+            }
+        }
+    }
+
+    fn mod_slot_at(&mut self, idx: usize) -> Result<&mut ModSlot, JsValue> {
+        self.mod_matrix.slots.get_mut(idx).ok_or_else(|| format!("No mod slot at {}", idx).into())
+    }
+
+    fn lfo_at(&mut self, idx: usize) -> Result<&mut Lfo, JsValue> {
+        self.mod_matrix.lfos.get_mut(idx).ok_or_else(|| format!("No LFO at {}", idx).into())
+    }
+
+    fn mseg_at(&mut self, idx: usize) -> Result<&mut MsegPlayer, JsValue> {
+        self.msegs.get_mut(idx).ok_or_else(|| format!("No MSEG at {}", idx).into())
+    }
+
+    #[wasm_bindgen]
+    pub fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    /// Ids of the bus controls making up its parameters
+    #[wasm_bindgen]
+    pub fn parameters(&self) -> js_sys::Array {
+        let params = js_sys::Array::new();
+        for id in Instrument::parameters(self) {
+            params.push(&id.into());
+        }
+        params
     }
 
     #[wasm_bindgen]
-    pub fn connect_to_mixer(&self, mixer: &Mixer, at: usize) {
-        audio::connect_to_one(self, mixer, at);
+    pub fn connect_to_mixer(&self, mixer: &Mixer, at: usize) -> Result<(), JsValue> {
+        Instrument::connect_to_mixer(self, mixer, at)
+    }
+
+    /// Id of the bus control `id` of this Subjam
+    fn control(&self, id: &str) -> String {
+        format!("{}.{}", self.name, id)
+    }
+}
+
+impl Instrument for Subjam {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn note_on(&mut self, note: u8, velocity: u8) {
+        Subjam::note_on(self, note, velocity);
+    }
+
+    fn note_off(&mut self, note: u8) {
+        Subjam::note_off(self, note);
+    }
+
+    fn tick(&mut self) {
+        Subjam::tick(self);
+    }
+
+    fn save_patch(&self) -> Result<JsValue, JsValue> {
+        Subjam::save_patch(self)
+    }
+
+    fn load_patch(&mut self, patch: &JsValue) -> Result<(), JsValue> {
+        Subjam::load_patch(self, patch)
+    }
+}
+
+impl Drop for Subjam {
+    fn drop(&mut self) {
+        let bus = unsafe { get_bus() };
+        bus.remove(&format!("{}.", self.name));
+        self.osc1.off();
+        self.osc2.off();
+        self.noise_voices.off();
+        self.noise.off();
+        self.out.disconnect().unwrap_or(());
     }
 }
 
@@ -733,3 +2995,54 @@ pub fn run() -> Result<(), JsValue> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_envelope_sweeps_up_from_the_cutoff() {
+        let targets = filter_envelope_targets(0.0, 2400.0, 0.25);
+        assert_eq!(targets, FilterEnvelopeTargets { peak: 2400.0, sustain: 600.0, rest: 0.0 });
+    }
+
+    #[test]
+    fn filter_envelope_sweeps_down_with_negative_amounts() {
+        let targets = filter_envelope_targets(0.0, -2400.0, 0.25);
+        assert_eq!(targets, FilterEnvelopeTargets { peak: -2400.0, sustain: -600.0, rest: 0.0 });
+        assert!(targets.peak < targets.sustain && targets.sustain < targets.rest);
+    }
+
+    #[test]
+    fn filter_envelope_sweeps_around_the_tracked_cutoff() {
+        // An octave above the key center, fully tracked
+        let offset = key_tracking(72, KEY_CENTER, 1.0);
+        assert_eq!(offset, 1200.0);
+        let up = filter_envelope_targets(offset, 1800.0, 0.5);
+        assert_eq!(up, FilterEnvelopeTargets { peak: 3000.0, sustain: 2100.0, rest: 1200.0 });
+        let down = filter_envelope_targets(offset, -1800.0, 0.5);
+        assert_eq!(down, FilterEnvelopeTargets { peak: -600.0, sustain: 300.0, rest: 1200.0 });
+
+        // Half tracked, an octave below
+        let offset = key_tracking(48, KEY_CENTER, 0.5);
+        let down = filter_envelope_targets(offset, -1200.0, 1.0);
+        assert_eq!(down, FilterEnvelopeTargets { peak: -1800.0, sustain: -1800.0, rest: -600.0 });
+    }
+
+    #[test]
+    fn filter_envelope_without_amount_stays_on_the_cutoff() {
+        let targets = filter_envelope_targets(-350.0, 0.0, 0.7);
+        assert_eq!(targets, FilterEnvelopeTargets { peak: -350.0, sustain: -350.0, rest: -350.0 });
+    }
+
+    #[test]
+    fn filter_envelope_level_inverts_detune() {
+        for &amount in &[2400.0, -1200.0, 50.0] {
+            for &level in &[0.0, 0.25, 0.5, 1.0] {
+                let detune = filter_envelope_detune(700.0, amount, level);
+                assert!((filter_envelope_level(700.0, amount, detune) - level).abs() < 1e-5);
+            }
+        }
+        assert_eq!(filter_envelope_level(700.0, 0.0, 1500.0), 0.0);
+    }
+}
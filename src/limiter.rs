@@ -0,0 +1,80 @@
+use wasm_bindgen::prelude::*;
+use web_sys::{AnalyserNode, AudioContext, AudioNode, DynamicsCompressorNode, WaveShaperNode};
+use crate::audio::{AudioInput, AudioOutput};
+
+/// Level (in dBFS) above which peaks get limited
+const THRESHOLD: f32 = -1.0;
+/// In seconds
+const RELEASE: f32 = 0.1;
+/// Number of samples checked for clipping at a time
+const CLIP_WINDOW: u32 = 2048;
+
+/// A brickwall limiter: a fast, hard compressor followed by a clipper at 0 dBFS
+/// catching whatever gets through it. Keeps count of how many times its input clipped.
+pub struct Limiter {
+    analyser: AnalyserNode,
+    compressor: DynamicsCompressorNode,
+    shaper: WaveShaperNode,
+    samples: Vec<f32>,
+    clipping: bool,
+    pub clips: u32,
+}
+
+impl Limiter {
+    pub fn new(ctx: &AudioContext) -> Result<Limiter, JsValue> {
+        let now = ctx.current_time();
+        let analyser = ctx.create_analyser()?;
+        analyser.set_fft_size(CLIP_WINDOW);
+
+        let compressor = ctx.create_dynamics_compressor()?;
+        compressor.threshold().set_value_at_time(THRESHOLD, now)?;
+        compressor.knee().set_value_at_time(0.0, now)?;
+        compressor.ratio().set_value_at_time(20.0, now)?;
+        compressor.attack().set_value_at_time(0.0, now)?;
+        compressor.release().set_value_at_time(RELEASE, now)?;
+
+        // Passes -1 to 1 through untouched and holds anything beyond at the edges
+        let shaper = ctx.create_wave_shaper()?;
+        let mut curve = [-1.0, 1.0];
+        shaper.set_curve(Some(&mut curve));
+
+        analyser.connect_with_audio_node(&compressor)?;
+        compressor.connect_with_audio_node(&shaper)?;
+
+        Ok(Limiter {
+            analyser,
+            compressor,
+            shaper,
+            samples: vec![0.0; CLIP_WINDOW as usize],
+            clipping: false,
+            clips: 0,
+        })
+    }
+
+    /// Checks the latest input for samples at or over 0 dBFS, counting every run of them once
+    pub fn check(&mut self) {
+        self.analyser.get_float_time_domain_data(&mut self.samples);
+        let clipping = self.samples.iter().any(|s| s.abs() >= 1.0);
+        if clipping && !self.clipping {
+            self.clips += 1;
+        }
+        self.clipping = clipping;
+    }
+
+    /// How much the compressor is currently turning the signal down, in dB
+    pub fn reduction(&self) -> f32 {
+        self.compressor.reduction()
+    }
+}
+
+impl AudioInput for Limiter {
+    fn input(&self) -> AudioNode {
+        self.analyser.clone().into()
+    }
+}
+
+impl AudioOutput for Limiter {
+    fn output(&self) -> AudioNode {
+        self.shaper.clone().into()
+    }
+}
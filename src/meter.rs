@@ -0,0 +1,97 @@
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+use web_sys::{AnalyserNode, AudioContext, AudioNode};
+
+/// Number of samples measured at a time, about 20ms at 48kHz
+const WINDOW: u32 = 1024;
+/// Floor of the levels, in dBFS, standing in for silence
+const MIN_DB: f32 = -96.0;
+/// How long (in seconds) the peak hold stays up before following the peak again
+const HOLD: f64 = 1.5;
+
+pub fn to_db(amplitude: f32) -> f32 {
+    (20.0 * amplitude.log10()).max(MIN_DB)
+}
+
+/// Levels of a signal in dBFS, and whether it clipped since the last reset
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Level {
+    pub peak: f32,
+    pub rms: f32,
+    pub peak_hold: f32,
+    pub clip: bool,
+}
+
+/// Levels of every strip of a mixer
+#[derive(Debug, Serialize)]
+pub struct Meters {
+    pub channels: Vec<Level>,
+    pub auxes: Vec<Level>,
+    pub master: Level,
+}
+
+/// Measures the levels of both sides of a stereo signal, reporting the louder one's peak
+pub struct Meter {
+    left: AnalyserNode,
+    right: AnalyserNode,
+    samples: Vec<f32>,
+    hold: f32,
+    held_at: f64,
+    clipped: bool,
+}
+
+impl Meter {
+    /// Creates a meter listening to `from`
+    pub fn new(ctx: &AudioContext, from: &AudioNode) -> Result<Meter, JsValue> {
+        let splitter = ctx.create_channel_splitter_with_number_of_outputs(2)?;
+        let left = ctx.create_analyser()?;
+        let right = ctx.create_analyser()?;
+        left.set_fft_size(WINDOW);
+        right.set_fft_size(WINDOW);
+        from.connect_with_audio_node(&splitter)?;
+        splitter.connect_with_audio_node_and_output(&left, 0)?;
+        splitter.connect_with_audio_node_and_output(&right, 1)?;
+
+        Ok(Meter {
+            left,
+            right,
+            samples: vec![0.0; WINDOW as usize],
+            hold: 0.0,
+            held_at: 0.0,
+            clipped: false,
+        })
+    }
+
+    /// Measures the latest samples, `now` being the audio context's current time
+    pub fn measure(&mut self, now: f64) -> Level {
+        let mut peak: f32 = 0.0;
+        let mut square_sum = 0.0;
+        for analyser in &[&self.left, &self.right] {
+            analyser.get_float_time_domain_data(&mut self.samples);
+            for s in &self.samples {
+                peak = peak.max(s.abs());
+                square_sum += s * s;
+            }
+        }
+        let rms = (square_sum / (2 * WINDOW) as f32).sqrt();
+
+        if peak >= self.hold || now - self.held_at > HOLD {
+            self.hold = peak;
+            self.held_at = now;
+        }
+        self.clipped |= peak >= 1.0;
+
+        Level {
+            peak: to_db(peak),
+            rms: to_db(rms),
+            peak_hold: to_db(self.hold),
+            clip: self.clipped,
+        }
+    }
+
+    /// Clears the clip indicator and the peak hold
+    pub fn reset(&mut self) {
+        self.clipped = false;
+        self.hold = 0.0;
+    }
+}
@@ -0,0 +1,168 @@
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+/// Number of LFOs available as modulation sources
+const LFOS: usize = 2;
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ModSource {
+    /// -1 to 1
+    Lfo1 = 0,
+    /// -1 to 1
+    Lfo2 = 1,
+    /// Per voice, 0 to 1
+    AmpEnv = 2,
+    /// Per voice, 0 to 1
+    FilterEnv = 3,
+    /// Per voice, 0 to 1 after the velocity curve
+    Velocity = 4,
+    /// Per voice, in octaves from the key center
+    Key = 5,
+    /// Per voice, 0 to 1
+    Aftertouch = 6,
+    /// 0 to 1
+    ModWheel = 7,
+    /// Per voice, -1 to 1, drawn on every note
+    Random = 8,
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum LfoShape {
+    Sine = 0,
+    Triangle = 1,
+    Square = 2,
+    Sawtooth = 3,
+}
+
+/// A free-running, control rate LFO
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Lfo {
+    pub shape: LfoShape,
+    /// In Hz
+    pub rate: f32,
+    #[serde(skip)]
+    phase: f64,
+}
+
+impl Default for Lfo {
+    fn default() -> Lfo {
+        Lfo { shape: LfoShape::Sine, rate: 1.0, phase: 0.0 }
+    }
+}
+
+impl Lfo {
+    pub fn advance(&mut self, seconds: f64) {
+        self.phase = (self.phase + seconds * self.rate as f64).fract();
+    }
+
+    pub fn value(&self) -> f32 {
+        let p = self.phase as f32;
+        match self.shape {
+            LfoShape::Sine => (p * 2.0 * std::f32::consts::PI).sin(),
+            LfoShape::Triangle => 1.0 - 4.0 * (p - 0.5).abs(),
+            LfoShape::Square => if p < 0.5 { 1.0 } else { -1.0 },
+            LfoShape::Sawtooth => 2.0 * p - 1.0,
+        }
+    }
+}
+
+/// Values of the per-voice sources for one voice
+#[derive(Clone, Copy, Debug, Default)]
+pub struct VoiceSources {
+    pub amp_env: f32,
+    pub filter_env: f32,
+    pub velocity: f32,
+    pub key: f32,
+    pub aftertouch: f32,
+    pub random: f32,
+}
+
+/// Modulates `destination`, a bus control id, by `source` times `amount`, scaled by `via` if set.
+///
+/// Amounts are in the destination's units, e.g. cents for pitch and cutoff.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ModSlot {
+    pub source: ModSource,
+    #[serde(default)]
+    pub via: Option<ModSource>,
+    pub destination: String,
+    pub amount: f32,
+}
+
+/// Slots routing sources to bus controls.
+///
+/// Per-voice sources modulate voice controls voice by voice. Global controls get
+/// them from the last voice played.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModMatrix {
+    pub slots: Vec<ModSlot>,
+    pub lfos: Vec<Lfo>,
+    #[serde(skip)]
+    pub mod_wheel: f32,
+    /// Destinations modulated on the last update, to reset the ones no longer modulated
+    #[serde(skip)]
+    active: Vec<String>,
+    #[serde(skip)]
+    last_update: Option<f64>,
+}
+
+impl Default for ModMatrix {
+    fn default() -> ModMatrix {
+        ModMatrix {
+            slots: vec![],
+            lfos: vec![Default::default(); LFOS],
+            mod_wheel: 0.0,
+            active: vec![],
+            last_update: None,
+        }
+    }
+}
+
+impl ModMatrix {
+    /// Moves the LFOs along to `now`, in seconds, returning the time elapsed since the last call
+    pub fn advance(&mut self, now: f64) -> f64 {
+        let elapsed = now - self.last_update.unwrap_or(now);
+        self.last_update = Some(now);
+        for lfo in &mut self.lfos {
+            lfo.advance(elapsed);
+        }
+        elapsed
+    }
+
+    pub fn source(&self, source: ModSource, voice: &VoiceSources) -> f32 {
+        match source {
+            ModSource::Lfo1 => self.lfos.get(0).map_or(0.0, |l| l.value()),
+            ModSource::Lfo2 => self.lfos.get(1).map_or(0.0, |l| l.value()),
+            ModSource::AmpEnv => voice.amp_env,
+            ModSource::FilterEnv => voice.filter_env,
+            ModSource::Velocity => voice.velocity,
+            ModSource::Key => voice.key,
+            ModSource::Aftertouch => voice.aftertouch,
+            ModSource::ModWheel => self.mod_wheel,
+            ModSource::Random => voice.random,
+        }
+    }
+
+    /// Sum of every slot modulating `destination`, for a voice with `voice` sources
+    pub fn value(&self, destination: &str, voice: &VoiceSources) -> f32 {
+        self.slots.iter().filter(|s| s.destination == destination).map(|s| {
+            let via = s.via.map_or(1.0, |v| self.source(v, voice));
+            self.source(s.source, voice) * via * s.amount
+        }).sum()
+    }
+
+    /// Destinations to update: the modulated ones plus those that just stopped being
+    pub fn destinations(&mut self) -> Vec<String> {
+        let mut current: Vec<String> = self.slots.iter().map(|s| s.destination.clone()).collect();
+        current.sort();
+        current.dedup();
+        let mut destinations = std::mem::replace(&mut self.active, current.clone());
+        destinations.retain(|d| !current.contains(d));
+        destinations.extend(current);
+        destinations
+    }
+}
@@ -0,0 +1,336 @@
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+use web_sys::AudioParam;
+
+use crate::envelope::{curve, ramp, CurveShape};
+use crate::get_bus;
+
+/// How far ahead loops get scheduled on an `AudioParam`, in seconds
+const LOOP_HORIZON: f64 = 60.0;
+/// Upper bound on loops scheduled ahead, for very short loops
+const MAX_LOOPS: usize = 256;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Breakpoint {
+    /// Milliseconds since the envelope started
+    pub time: f32,
+    /// Out of 1
+    pub value: f32,
+    /// Shape of the segment leading to this point
+    pub shape: CurveShape,
+    pub tension: f32,
+}
+
+/// A multi-segment envelope: breakpoints with an optional sustain point and loop.
+///
+/// While held, playback loops between `loop_start` and `loop_end` if both are set,
+/// otherwise it stops at `sustain`. On release it carries on past the sustain point
+/// (or the loop end) from wherever it was. Without either, release has no effect.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Mseg {
+    pub points: Vec<Breakpoint>,
+    pub sustain: Option<usize>,
+    pub loop_start: Option<usize>,
+    pub loop_end: Option<usize>,
+}
+
+impl Mseg {
+    /// Checks there are points, their times are sorted and not negative, and the sustain and
+    /// loop points are among them
+    pub fn validate(&self) -> Result<(), JsValue> {
+        if self.points.is_empty() {
+            return Err("An MSEG needs at least one point".into());
+        }
+        let mut last = 0.0;
+        for (idx, p) in self.points.iter().enumerate() {
+            if p.time.is_nan() || p.time < last || !p.value.is_finite() {
+                return Err(format!("Point {} of the MSEG is at {}ms, which is negative or before the previous point", idx, p.time).into());
+            }
+            last = p.time;
+        }
+        for (what, idx) in &[("sustain", self.sustain), ("loop start", self.loop_start), ("loop end", self.loop_end)] {
+            if let Some(idx) = idx {
+                if *idx >= self.points.len() {
+                    return Err(format!("The MSEG's {} is point {}, but there are {}", what, idx, self.points.len()).into());
+                }
+            }
+        }
+        if let (Some(start), Some(end)) = (self.loop_start, self.loop_end) {
+            if start >= end {
+                return Err(format!("The MSEG's loop starts at point {}, not before its end at {}", start, end).into());
+            }
+        }
+        Ok(())
+    }
+
+    fn time(&self, idx: usize) -> f32 {
+        self.points[idx].time
+    }
+
+    fn looping(&self) -> Option<(usize, usize)> {
+        match (self.loop_start, self.loop_end) {
+            (Some(s), Some(e)) if s < e && e < self.points.len() && self.time(e) > self.time(s) => Some((s, e)),
+            _ => None,
+        }
+    }
+
+    /// The point playback continues from when released, if release does anything
+    fn release_point(&self) -> Option<usize> {
+        self.sustain.filter(|s| *s < self.points.len())
+            .or_else(|| self.looping().map(|(_, e)| e))
+    }
+
+    pub fn has_release(&self) -> bool {
+        self.release_point().is_some()
+    }
+
+    /// Value along the segment ending at `idx`, `t` ms into it, starting from `from`
+    fn segment(&self, idx: usize, from: f32, t: f32) -> f32 {
+        let p = &self.points[idx];
+        let len = p.time - self.time(idx - 1);
+        if len <= 0.0 {
+            return p.value;
+        }
+        from + (p.value - from) * curve(p.shape, p.tension, (t / len).max(0.0).min(1.0))
+    }
+
+    /// Value at `t` ms along the breakpoints, ignoring sustain and loops
+    fn at(&self, t: f32) -> f32 {
+        if t <= self.time(0) {
+            return self.points[0].value;
+        }
+        for idx in 1..self.points.len() {
+            if t <= self.time(idx) {
+                return self.segment(idx, self.points[idx - 1].value, t - self.time(idx - 1));
+            }
+        }
+        self.points[self.points.len() - 1].value
+    }
+
+    /// Value `t` ms after starting, while still held
+    pub fn held(&self, t: f32) -> f32 {
+        if self.points.is_empty() {
+            return 0.0;
+        }
+        if let Some((s, e)) = self.looping() {
+            let (ts, te) = (self.time(s), self.time(e));
+            if t > te {
+                return self.at(ts + (t - ts) % (te - ts));
+            }
+        } else if let Some(s) = self.sustain.filter(|s| *s < self.points.len()) {
+            if t >= self.time(s) {
+                return self.points[s].value;
+            }
+        }
+        self.at(t)
+    }
+
+    /// Value `t` ms after being released at `from`
+    pub fn released(&self, from: f32, t: f32) -> f32 {
+        let r = match self.release_point() {
+            Some(r) => r,
+            None => return from,
+        };
+        if r + 1 >= self.points.len() {
+            return from;
+        }
+        if t <= self.time(r + 1) - self.time(r) {
+            return self.segment(r + 1, from, t);
+        }
+        self.at(self.time(r) + t)
+    }
+
+    /// Schedules the held part of the envelope on `param` from `start`, mapping values with `map`.
+    ///
+    /// Only the first loops get scheduled (see `schedule_loops`), returning the loop to carry on from.
+    pub fn schedule_start<F: Fn(f32) -> f32>(&self, param: &AudioParam, start: f64, map: F) -> usize {
+        if self.points.is_empty() {
+            return 1;
+        }
+        param.cancel_scheduled_values(start).unwrap();
+        param.set_value_at_time(map(self.points[0].value), start + self.time(0) as f64 / 1000.0).unwrap();
+
+        let last = match (self.looping(), self.sustain) {
+            (Some((_, e)), _) => e,
+            (None, Some(s)) if s < self.points.len() => s,
+            _ => self.points.len() - 1,
+        };
+        self.schedule_segments(param, start, 1, last, &map);
+        self.schedule_loops(param, start, 1, start, &map)
+    }
+
+    /// Schedules loops from the `next`th on, for an envelope started at `start`, as far as
+    /// `LOOP_HORIZON` (or `MAX_LOOPS` loops) ahead of `now`. Returns the loop to carry on from.
+    pub fn schedule_loops<F: Fn(f32) -> f32>(&self, param: &AudioParam, start: f64, mut next: usize, now: f64, map: &F) -> usize {
+        let (s, e) = match self.looping() {
+            Some(l) => l,
+            None => return next,
+        };
+        let period = (self.time(e) - self.time(s)) as f64 / 1000.0;
+        let until = now + LOOP_HORIZON.min(period * MAX_LOOPS as f64);
+        loop {
+            let offset = period * next as f64;
+            let loop_start = start + offset + self.time(s) as f64 / 1000.0;
+            if loop_start >= until {
+                return next;
+            }
+            param.set_value_at_time(map(self.points[s].value), loop_start).unwrap();
+            self.schedule_segments(param, start + offset, s + 1, e, map);
+            next += 1;
+        }
+    }
+
+    /// Schedules the release on `param` from wherever it is at `now`
+    pub fn schedule_release<F: Fn(f32) -> f32>(&self, param: &AudioParam, now: f64, map: F) {
+        let r = match self.release_point() {
+            Some(r) if r + 1 < self.points.len() => r,
+            _ => return,
+        };
+        let from = param.value();
+        param.cancel_scheduled_values(now).unwrap();
+        param.set_value_at_time(from, now).unwrap();
+
+        let p = &self.points[r + 1];
+        let len = (p.time - self.time(r)) as f64 / 1000.0;
+        ramp(param, from, map(p.value), now, len, p.shape, p.tension);
+        self.schedule_segments(param, now - self.time(r) as f64 / 1000.0, r + 2, self.points.len() - 1, &map);
+    }
+
+    /// Schedules segments ending at `from..=to`, with point times relative to `start`
+    fn schedule_segments<F: Fn(f32) -> f32>(&self, param: &AudioParam, start: f64, from: usize, to: usize, map: &F) {
+        for idx in from..=to {
+            let prev = &self.points[idx - 1];
+            let p = &self.points[idx];
+            let at = start + prev.time as f64 / 1000.0;
+            let len = (p.time - prev.time) as f64 / 1000.0;
+            ramp(param, map(prev.value), map(p.value), at, len, p.shape, p.tension);
+        }
+    }
+}
+
+enum MsegTarget {
+    Param(AudioParam),
+    Control(String),
+}
+
+/// Plays an `Mseg` onto an `AudioParam` or a bus control, mapping its values to `min..max`.
+///
+/// Params are automated ahead of time, and controls on every `tick`. Either way `tick` needs
+/// calling regularly, as params only get their loops scheduled so far ahead.
+#[wasm_bindgen]
+pub struct MsegPlayer {
+    mseg: Mseg,
+    target: MsegTarget,
+    min: f32,
+    max: f32,
+    started: Option<f64>,
+    released: Option<(f64, f32)>,
+    /// Next loop to schedule on a param
+    next_loop: usize,
+}
+
+impl MsegPlayer {
+    pub fn mseg(&self) -> &Mseg {
+        &self.mseg
+    }
+
+    pub fn set(&mut self, mseg: Mseg) -> Result<(), JsValue> {
+        mseg.validate()?;
+        self.mseg = mseg;
+        Ok(())
+    }
+
+    /// The bus control this player drives, if any
+    pub fn control(&self) -> Option<&str> {
+        match &self.target {
+            MsegTarget::Control(id) => Some(id),
+            MsegTarget::Param(_) => None,
+        }
+    }
+
+    pub fn range(&self) -> (f32, f32) {
+        (self.min, self.max)
+    }
+
+    fn map(&self, v: f32) -> f32 {
+        self.min + v * (self.max - self.min)
+    }
+
+    /// Unmapped value at `now`
+    fn value(&self, now: f64) -> f32 {
+        let started = match self.started {
+            Some(s) => s,
+            None => return self.mseg.held(0.0),
+        };
+        match self.released {
+            Some((at, from)) => self.mseg.released(from, ((now - at) * 1000.0) as f32),
+            None => self.mseg.held(((now - started) * 1000.0) as f32),
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl MsegPlayer {
+    pub fn for_param(param: AudioParam, min: f32, max: f32) -> MsegPlayer {
+        MsegPlayer { mseg: Default::default(), target: MsegTarget::Param(param), min, max, started: None, released: None, next_loop: 1 }
+    }
+
+    pub fn for_control(id: String, min: f32, max: f32) -> MsegPlayer {
+        MsegPlayer { mseg: Default::default(), target: MsegTarget::Control(id), min, max, started: None, released: None, next_loop: 1 }
+    }
+
+    /// Sets the breakpoints, sustain and loop points from `{points, sustain, loop_start, loop_end}`.
+    ///
+    /// Points need sorted times in ms from 0, and the sustain and loop points are their indices.
+    #[wasm_bindgen]
+    pub fn set_mseg(&mut self, mseg: &JsValue) -> Result<(), JsValue> {
+        let mseg: Mseg = mseg.into_serde().map_err(|e| JsValue::from(e.to_string()))?;
+        self.set(mseg)
+    }
+
+    #[wasm_bindgen]
+    pub fn get_mseg(&self) -> Result<JsValue, JsValue> {
+        JsValue::from_serde(&self.mseg).map_err(|e| e.to_string().into())
+    }
+
+    #[wasm_bindgen]
+    pub fn start(&mut self, now: f64) {
+        self.started = Some(now);
+        self.released = None;
+        if let MsegTarget::Param(param) = &self.target {
+            self.next_loop = self.mseg.schedule_start(param, now, |v| self.map(v));
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn release(&mut self, now: f64) {
+        if self.started.is_none() || self.released.is_some() || !self.mseg.has_release() {
+            return;
+        }
+        let from = self.value(now);
+        self.released = Some((now, from));
+        if let MsegTarget::Param(param) = &self.target {
+            self.mseg.schedule_release(param, now, |v| self.map(v));
+        }
+    }
+
+    /// Pushes the current value to the bus control being driven, or schedules more loops on
+    /// the param being driven
+    #[wasm_bindgen]
+    pub fn tick(&mut self, now: f64) {
+        let started = match self.started {
+            Some(s) => s,
+            None => return,
+        };
+        match &self.target {
+            MsegTarget::Control(id) => {
+                let bus = unsafe { get_bus() };
+                bus.drive(id.clone(), self.map(self.value(now)));
+            },
+            MsegTarget::Param(param) => if self.released.is_none() {
+                self.next_loop = self.mseg.schedule_loops(param, started, self.next_loop, now, &|v| self.map(v));
+            },
+        }
+    }
+}
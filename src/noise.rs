@@ -0,0 +1,117 @@
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+use web_sys::{AudioBuffer, AudioBufferSourceNode, AudioContext, AudioNode, GainNode};
+use crate::audio::AudioOutput;
+use crate::smooth;
+
+/// Length of the looping noise buffers, in seconds
+const NOISE_LENGTH: f32 = 2.0;
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum NoiseColor {
+    White = 0,
+    Pink = 1,
+    Brown = 2,
+}
+
+fn white(len: usize) -> Vec<f32> {
+    (0..len).map(|_| js_sys::Math::random() as f32 * 2.0 - 1.0).collect()
+}
+
+/// Filters white noise down 3dB per octave (Paul Kellet's refined method)
+fn pink(white: &[f32]) -> Vec<f32> {
+    let mut b = [0f32; 7];
+    white.iter().map(|w| {
+        b[0] = 0.99886 * b[0] + w * 0.0555179;
+        b[1] = 0.99332 * b[1] + w * 0.0750759;
+        b[2] = 0.96900 * b[2] + w * 0.153852;
+        b[3] = 0.86650 * b[3] + w * 0.3104856;
+        b[4] = 0.55000 * b[4] + w * 0.5329522;
+        b[5] = -0.7616 * b[5] - w * 0.0168980;
+        let out = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + w * 0.5362;
+        b[6] = w * 0.115926;
+        out * 0.11
+    }).collect()
+}
+
+/// Integrates white noise, leaking slightly so it doesn't drift away from 0
+fn brown(white: &[f32]) -> Vec<f32> {
+    let mut last = 0f32;
+    white.iter().map(|w| {
+        last = (last + 0.02 * w) / 1.02;
+        last * 3.5
+    }).collect()
+}
+
+/// A noise source playing looping buffers of white, pink or brown noise.
+pub struct Noise {
+    ctx: AudioContext,
+    sources: Vec<AudioBufferSourceNode>,
+    gains: Vec<GainNode>,
+    out: GainNode,
+    pub color: NoiseColor,
+}
+
+impl Noise {
+    pub fn new(ctx: AudioContext) -> Result<Noise, JsValue> {
+        let sample_rate = ctx.sample_rate();
+        let len = (sample_rate * NOISE_LENGTH) as usize;
+        let w = white(len);
+        let colors = vec![w.clone(), pink(&w), brown(&w)];
+
+        let out = ctx.create_gain()?;
+        let mut sources = vec![];
+        let mut gains = vec![];
+        for mut data in colors {
+            let buffer: AudioBuffer = ctx.create_buffer(1, len as u32, sample_rate)?;
+            buffer.copy_to_channel(&mut data, 0)?;
+            let source = ctx.create_buffer_source()?;
+            source.set_buffer(Some(&buffer));
+            source.set_loop(true);
+            let gain = ctx.create_gain()?;
+            gain.gain().set_value_at_time(0.0, ctx.current_time())?;
+            source.connect_with_audio_node(&gain)?;
+            gain.connect_with_audio_node(&out)?;
+            sources.push(source);
+            gains.push(gain);
+        }
+
+        let mut n = Noise {
+            ctx,
+            sources,
+            gains,
+            out,
+            color: NoiseColor::White,
+        };
+        n.set_color(NoiseColor::White);
+        Ok(n)
+    }
+
+    pub fn on(&self) {
+        for s in &self.sources {
+            s.start().unwrap();
+        }
+    }
+
+    pub fn off(&self) {
+        for s in &self.sources {
+            s.stop().unwrap();
+        }
+    }
+
+    pub fn set_color(&mut self, color: NoiseColor) {
+        self.color = color;
+        let now = self.ctx.current_time();
+        for (idx, g) in self.gains.iter().enumerate() {
+            let level = if idx == color as usize { 1.0 } else { 0.0 };
+            smooth::set(&g.gain(), level, now, smooth::GAIN);
+        }
+    }
+}
+
+impl AudioOutput for Noise {
+    fn output(&self) -> AudioNode {
+        self.out.clone().into()
+    }
+}
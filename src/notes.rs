@@ -0,0 +1,66 @@
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum VoiceMode {
+    Poly = 0,
+    /// One voice, retriggering the envelopes on every note change
+    Mono = 1,
+    /// One voice, only triggering the envelopes when no other note is held
+    Legato = 2,
+}
+
+/// Which held note sounds in mono and legato modes
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum NotePriority {
+    Last = 0,
+    Low = 1,
+    High = 2,
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum GlideMode {
+    Always = 0,
+    /// Only glide between overlapping notes
+    Legato = 1,
+}
+
+/// Notes held down in mono and legato modes, in the order they were pressed.
+#[derive(Default)]
+pub struct NoteStack {
+    notes: Vec<(u8, u8)>,
+}
+
+impl NoteStack {
+    pub fn push(&mut self, note: u8, velocity: u8) {
+        self.remove(note);
+        self.notes.push((note, velocity));
+    }
+
+    /// Returns whether the note was held
+    pub fn remove(&mut self, note: u8) -> bool {
+        let len = self.notes.len();
+        self.notes.retain(|(n, _)| *n != note);
+        self.notes.len() != len
+    }
+
+    pub fn clear(&mut self) {
+        self.notes.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.notes.is_empty()
+    }
+
+    /// The (note, velocity) that should be sounding given `priority`
+    pub fn current(&self, priority: NotePriority) -> Option<(u8, u8)> {
+        match priority {
+            NotePriority::Last => self.notes.last().cloned(),
+            NotePriority::Low => self.notes.iter().min_by_key(|(n, _)| *n).cloned(),
+            NotePriority::High => self.notes.iter().max_by_key(|(n, _)| *n).cloned(),
+        }
+    }
+}
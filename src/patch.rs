@@ -0,0 +1,98 @@
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+use web_sys::{BiquadFilterType, OscillatorType};
+
+use crate::envelope::Envelope;
+use crate::mseg::Mseg;
+use crate::noise::NoiseColor;
+use crate::notes::{GlideMode, NotePriority, VoiceMode};
+use crate::modulation::ModMatrix;
+use crate::velocity::Velocity;
+
+/// Everything needed to restore a `Subjam`'s sound.
+///
+/// WebAudio enums are stored by their JS names, e.g. `"sawtooth"` or `"lowpass"`.
+#[derive(Serialize, Deserialize)]
+pub struct Patch {
+    pub osc1_type: String,
+    pub osc2_type: String,
+    pub osc_mix: f32,
+    pub sync: bool,
+    pub sync_ratio: f32,
+    pub sub_type: String,
+    pub sub_octave: u8,
+    pub sub_level: f32,
+    pub noise_color: NoiseColor,
+    pub noise_level: f32,
+    pub voice_mode: VoiceMode,
+    pub note_priority: NotePriority,
+    pub glide_mode: GlideMode,
+    pub glide_time: u32,
+    pub filter_type: String,
+    pub filter_frequency: u32,
+    pub filter_resonance: f32,
+    /// In cents
+    #[serde(default = "default_filter_env_amount")]
+    pub filter_env_amount: f32,
+    #[serde(default)]
+    pub key_tracking: f32,
+    #[serde(default = "default_key_center")]
+    pub key_center: u8,
+    /// In cents
+    #[serde(default)]
+    pub velocity_cutoff: f32,
+    #[serde(default)]
+    pub velocity: Velocity,
+    #[serde(default)]
+    pub mod_matrix: ModMatrix,
+    #[serde(default)]
+    pub normalize: bool,
+    pub amp_env: Envelope,
+    pub filter_env: Envelope,
+    /// osc2's own envelopes and filter, when split from osc1
+    #[serde(default)]
+    pub split: Option<SplitPatch>,
+    #[serde(default)]
+    pub msegs: Vec<MsegPatch>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SplitPatch {
+    pub amp_env: Envelope,
+    pub filter_env: Envelope,
+    pub filter_type: String,
+    pub filter_frequency: u32,
+    pub filter_resonance: f32,
+}
+
+/// An MSEG and the bus control it drives
+#[derive(Serialize, Deserialize)]
+pub struct MsegPatch {
+    pub target: String,
+    pub min: f32,
+    pub max: f32,
+    pub mseg: Mseg,
+}
+
+fn default_filter_env_amount() -> f32 {
+    crate::FILTER_ENV_AMOUNT
+}
+
+fn default_key_center() -> u8 {
+    crate::KEY_CENTER
+}
+
+/// The JS name of a WebAudio enum value
+pub fn name<T: Into<JsValue>>(value: T) -> String {
+    value.into().as_string().unwrap()
+}
+
+pub fn oscillator_type(name: &str) -> Result<OscillatorType, JsValue> {
+    OscillatorType::from_js_value(&name.into())
+        .ok_or_else(|| format!("Unknown oscillator type {}", name).into())
+}
+
+pub fn filter_type(name: &str) -> Result<BiquadFilterType, JsValue> {
+    BiquadFilterType::from_js_value(&name.into())
+        .ok_or_else(|| format!("Unknown filter type {}", name).into())
+}
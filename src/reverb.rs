@@ -0,0 +1,473 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::f64::consts::PI;
+use std::rc::Rc;
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+use web_sys::{AudioBuffer, AudioContext, AudioNode, BiquadFilterNode, BiquadFilterType, ChannelCountMode,
+              ChannelInterpretation, ChannelMergerNode, ConvolverNode, DelayNode, GainNode};
+
+use crate::audio::{AudioInput, AudioOutput};
+use crate::effect::Effect;
+use crate::get_bus;
+use crate::smooth::{self, Ramp, Smoothing};
+
+/// Longest decay, in seconds, which is also the longest generated impulse response
+const MAX_DECAY: f32 = 10.0;
+/// Longest pre-delay, in seconds
+const MAX_PRE_DELAY: f64 = 0.5;
+/// How long the sound takes to build up in the largest room, in seconds
+const MAX_BUILD_UP: f32 = 0.08;
+/// Highest feedback of the algorithmic reverb's combs, keeping it from ringing forever
+const MAX_COMB_FEEDBACK: f32 = 0.98;
+/// Q (in dB) of the damping filters, flat up to their cutoff so they never boost the tail
+const DAMPING_Q: f32 = -3.01;
+/// Crossfade between impulse responses, which is also the shortest time between two of them
+const SWAP: Smoothing = Smoothing { ramp: Ramp::Linear, time: 0.05 };
+/// Insert mix it starts at, as it outputs the reverb only
+const DEFAULT_MIX: f32 = 0.25;
+
+/// Freeverb's tunings, in samples at 44.1kHz
+const FREEVERB_RATE: f64 = 44100.0;
+const COMBS: [f64; 8] = [1116.0, 1188.0, 1277.0, 1356.0, 1422.0, 1491.0, 1557.0, 1617.0];
+const ALLPASSES: [f64; 4] = [556.0, 441.0, 341.0, 225.0];
+/// How much longer the right side's delays are
+const STEREO_SPREAD: f64 = 23.0;
+/// Freeverb's input and wet gains, keeping its eight combs from piling up
+const FREEVERB_GAIN: f32 = 0.015 * 3.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ReverbMode {
+    /// Convolves with an impulse response generated from the parameters
+    Generated = 0,
+    /// Convolves with a loaded impulse response, or a generated one until there is one
+    Loaded = 1,
+    /// Freeverb's combs and allpasses, cheaper but metallic on short sounds
+    Algorithmic = 2,
+}
+
+impl ReverbMode {
+    fn from_value(value: f32) -> ReverbMode {
+        match value.round() as i32 {
+            1 => ReverbMode::Loaded,
+            2 => ReverbMode::Algorithmic,
+            _ => ReverbMode::Generated,
+        }
+    }
+}
+
+/// Everything needed to restore a reverb's sound, but a loaded impulse response
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReverbPatch {
+    pub mode: ReverbMode,
+    /// Time to fade by 60dB, in seconds
+    pub decay: f32,
+    /// In ms
+    pub pre_delay: f32,
+    /// 0 to 1
+    pub size: f32,
+    /// Frequency (in Hz) the tail is darkened down to
+    pub damping: f32,
+    /// How much of the reverb is heard over the dry signal, which is its insert slot's
+    pub mix: f32,
+}
+
+impl Default for ReverbPatch {
+    fn default() -> ReverbPatch {
+        ReverbPatch {
+            mode: ReverbMode::Generated,
+            decay: 2.0,
+            pre_delay: 10.0,
+            size: 0.5,
+            damping: 6000.0,
+            mix: DEFAULT_MIX,
+        }
+    }
+}
+
+impl ReverbPatch {
+    /// The patch of the reverb named `name`, from its controls
+    pub fn read(name: &str) -> ReverbPatch {
+        let bus = unsafe { get_bus() };
+        let value = |param: &str| bus.value(format!("{}.{}", name, param));
+        ReverbPatch {
+            mode: ReverbMode::from_value(value("mode")),
+            decay: value("decay"),
+            pre_delay: value("pre_delay"),
+            size: value("size"),
+            damping: value("damping"),
+            mix: value("mix"),
+        }
+    }
+
+    /// Sets the controls of the reverb named `name`
+    pub fn apply(&self, name: &str) {
+        let bus = unsafe { get_bus() };
+        let values = [
+            ("mode", self.mode as u8 as f32),
+            ("decay", self.decay),
+            ("pre_delay", self.pre_delay),
+            ("size", self.size),
+            ("damping", self.damping),
+            ("mix", self.mix),
+        ];
+        for (param, value) in values.iter() {
+            bus.trigger(format!("{}.{}", name, param), *value);
+        }
+    }
+}
+
+/// What an impulse response was made from, to tell when it needs replacing
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Impulse {
+    Generated { decay: f32, size: f32, damping: f32 },
+    Loaded,
+}
+
+/// A small xorshift generator, so the same parameters always generate the same impulse response
+struct Random(u32);
+
+impl Random {
+    /// Between -1 and 1
+    fn next(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0 as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+}
+
+/// Generates a stereo impulse response: noise fading by 60dB over `decay` seconds, building up
+/// for longer in larger rooms and darkening down to `damping` Hz as it fades
+fn generate(ctx: &AudioContext, decay: f32, size: f32, damping: f32) -> Result<AudioBuffer, JsValue> {
+    let sample_rate = ctx.sample_rate();
+    let len = ((sample_rate * decay) as usize).max(1);
+    let build_up = (size * MAX_BUILD_UP * sample_rate).max(1.0);
+    let buffer = ctx.create_buffer(2, len as u32, sample_rate)?;
+    for channel in 0..2 {
+        let mut random = Random(0x9e37_79b9 + channel);
+        let mut low = 0f32;
+        let mut data: Vec<f32> = (0..len).map(|i| {
+            let t = i as f32 / len as f32;
+            let cutoff = 20000.0 * (damping / 20000.0).powf(t);
+            let a = (-2.0 * PI * cutoff as f64 / sample_rate as f64).exp() as f32;
+            low = (1.0 - a) * random.next() + a * low;
+            let envelope = 10f32.powf(-3.0 * t) * (i as f32 / build_up).min(1.0);
+            low * envelope
+        }).collect();
+        buffer.copy_to_channel(&mut data, channel as i32)?;
+    }
+    Ok(buffer)
+}
+
+/// A lowpass-feedback comb filter
+struct Comb {
+    delay: DelayNode,
+    damper: BiquadFilterNode,
+    feedback: GainNode,
+    /// In seconds, before scaling by size
+    time: f64,
+}
+
+/// Freeverb: eight parallel lowpass-feedback combs into four allpasses in series, for each side
+struct Freeverb {
+    input: GainNode,
+    output: ChannelMergerNode,
+    combs: Vec<Comb>,
+    allpasses: Vec<DelayNode>,
+}
+
+impl Freeverb {
+    fn new(ctx: &AudioContext) -> Result<Freeverb, JsValue> {
+        let input = ctx.create_gain()?;
+        input.set_channel_count(1);
+        input.set_channel_count_mode(ChannelCountMode::Explicit);
+        input.set_channel_interpretation(ChannelInterpretation::Speakers);
+        input.gain().set_value_at_time(FREEVERB_GAIN, ctx.current_time())?;
+        let output = ctx.create_channel_merger_with_number_of_inputs(2)?;
+
+        let mut combs = vec![];
+        let mut allpasses = vec![];
+        for side in 0..2 {
+            let spread = side as f64 * STEREO_SPREAD;
+            let sum = ctx.create_gain()?;
+            for samples in COMBS.iter() {
+                let time = (samples + spread) / FREEVERB_RATE;
+                let delay = ctx.create_delay_with_max_delay_time(time * 2.0)?;
+                let damper = ctx.create_biquad_filter()?;
+                damper.set_type(BiquadFilterType::Lowpass);
+                damper.q().set_value_at_time(DAMPING_Q, ctx.current_time())?;
+                let feedback = ctx.create_gain()?;
+                input.connect_with_audio_node(&delay)?;
+                delay.connect_with_audio_node(&damper)?;
+                damper.connect_with_audio_node(&feedback)?;
+                feedback.connect_with_audio_node(&delay)?;
+                delay.connect_with_audio_node(&sum)?;
+                combs.push(Comb { delay, damper, feedback, time });
+            }
+
+            // Each allpass outputs its delayed signal less its input, feeding half the delayed signal back
+            let mut last: AudioNode = sum.into();
+            for samples in ALLPASSES.iter() {
+                let time = (samples + spread) / FREEVERB_RATE;
+                let into = ctx.create_gain()?;
+                let delay = ctx.create_delay_with_max_delay_time(time)?;
+                let feedback = ctx.create_gain()?;
+                let inverted = ctx.create_gain()?;
+                let out = ctx.create_gain()?;
+                delay.delay_time().set_value_at_time(time as f32, ctx.current_time())?;
+                feedback.gain().set_value_at_time(0.5, ctx.current_time())?;
+                inverted.gain().set_value_at_time(-1.0, ctx.current_time())?;
+                last.connect_with_audio_node(&into)?;
+                last.connect_with_audio_node(&inverted)?;
+                into.connect_with_audio_node(&delay)?;
+                delay.connect_with_audio_node(&feedback)?;
+                feedback.connect_with_audio_node(&into)?;
+                delay.connect_with_audio_node(&out)?;
+                inverted.connect_with_audio_node(&out)?;
+                last = out.into();
+                allpasses.push(delay);
+            }
+            last.connect_with_audio_node_and_output_and_input(&output, 0, side)?;
+        }
+
+        Ok(Freeverb { input, output, combs, allpasses })
+    }
+
+    /// Larger rooms lengthen the combs, whose feedback is set for the tail to fade by 60dB over `decay`
+    fn set(&self, decay: f32, size: f32, damping: f32, now: f64) {
+        let scale = 0.5 + size.max(0.0).min(1.0) as f64;
+        for comb in &self.combs {
+            let time = comb.time * scale;
+            let feedback = 10f32.powf(-3.0 * time as f32 / decay.max(0.01)).min(MAX_COMB_FEEDBACK);
+            smooth::set(&comb.delay.delay_time(), time as f32, now, smooth::TIME);
+            smooth::set(&comb.feedback.gain(), feedback, now, smooth::GAIN);
+            smooth::set(&comb.damper.frequency(), damping, now, smooth::FREQUENCY);
+        }
+    }
+}
+
+/// A bus control of the reverb: its name, initial value and how it changes the state
+type Param = (&'static str, f32, fn(&mut State, f32));
+
+struct State {
+    ctx: AudioContext,
+    mode: ReverbMode,
+    decay: f32,
+    /// In ms
+    pre_delay: f32,
+    size: f32,
+    damping: f32,
+    pre: DelayNode,
+    /// Two convolvers, to crossfade between impulse responses
+    convolvers: [ConvolverNode; 2],
+    convolver_gains: [GainNode; 2],
+    active: usize,
+    /// What the active convolver's impulse response was made from
+    current: Option<Impulse>,
+    swapped_at: f64,
+    loaded: Option<AudioBuffer>,
+    convolution: GainNode,
+    freeverb: Freeverb,
+    algorithmic: GainNode,
+}
+
+impl State {
+    /// What the impulse response should be made from, if convolving
+    fn wanted(&self) -> Option<Impulse> {
+        match self.mode {
+            ReverbMode::Algorithmic => None,
+            ReverbMode::Loaded if self.loaded.is_some() => Some(Impulse::Loaded),
+            _ => Some(Impulse::Generated { decay: self.decay, size: self.size, damping: self.damping }),
+        }
+    }
+
+    fn update(&self) {
+        let now = self.ctx.current_time();
+        let pre_delay = (self.pre_delay.max(0.0) as f64 / 1000.0).min(MAX_PRE_DELAY);
+        smooth::set(&self.pre.delay_time(), pre_delay as f32, now, smooth::TIME);
+        self.freeverb.set(self.decay, self.size, self.damping, now);
+        let algorithmic = if self.mode == ReverbMode::Algorithmic { 1.0 } else { 0.0 };
+        smooth::set(&self.algorithmic.gain(), algorithmic, now, SWAP);
+        smooth::set(&self.convolution.gain(), 1.0 - algorithmic, now, SWAP);
+    }
+
+    /// Crossfades to a convolver with the wanted impulse response
+    fn swap(&mut self, impulse: Impulse, now: f64) -> Result<(), JsValue> {
+        let buffer = match (impulse, &self.loaded) {
+            (Impulse::Loaded, Some(loaded)) => loaded.clone(),
+            _ => generate(&self.ctx, self.decay.max(0.1).min(MAX_DECAY), self.size, self.damping.max(20.0))?,
+        };
+        let next = 1 - self.active;
+        self.convolvers[next].set_buffer(Some(&buffer));
+        smooth::set(&self.convolver_gains[next].gain(), 1.0, now, SWAP);
+        smooth::set(&self.convolver_gains[self.active].gain(), 0.0, now, SWAP);
+        self.active = next;
+        self.current = Some(impulse);
+        self.swapped_at = now;
+        Ok(())
+    }
+}
+
+/// A stereo reverb, convolving with a generated or loaded impulse response, or algorithmic.
+/// It outputs the reverb only, leaving the dry signal to its insert slot's mix.
+///
+/// Its parameters are bus controls named after it: `mode` (see `ReverbMode`), `decay`
+/// (seconds), `pre_delay` (ms), `size` (0 to 1) and `damping` (Hz), e.g. `reverb.decay`
+/// for a reverb named `reverb`. Generating impulse responses is too heavy for every change,
+/// so they get replaced on `tick`.
+pub struct Reverb {
+    name: String,
+    input: GainNode,
+    output: GainNode,
+    state: Rc<RefCell<State>>,
+}
+
+impl Reverb {
+    pub fn new(name: String, ctx: AudioContext) -> Result<Reverb, JsValue> {
+        let bus = unsafe { get_bus() };
+        let input = ctx.create_gain()?;
+        input.set_channel_count(2);
+        input.set_channel_count_mode(ChannelCountMode::Explicit);
+        input.set_channel_interpretation(ChannelInterpretation::Speakers);
+        let output = ctx.create_gain()?;
+        let pre = ctx.create_delay_with_max_delay_time(MAX_PRE_DELAY)?;
+        input.connect_with_audio_node(&pre)?;
+
+        let convolution = ctx.create_gain()?;
+        let convolvers = [ctx.create_convolver()?, ctx.create_convolver()?];
+        let convolver_gains = [ctx.create_gain()?, ctx.create_gain()?];
+        for (convolver, gain) in convolvers.iter().zip(convolver_gains.iter()) {
+            gain.gain().set_value_at_time(0.0, ctx.current_time())?;
+            pre.connect_with_audio_node(convolver)?;
+            convolver.connect_with_audio_node(gain)?;
+            gain.connect_with_audio_node(&convolution)?;
+        }
+        convolution.connect_with_audio_node(&output)?;
+
+        let freeverb = Freeverb::new(&ctx)?;
+        let algorithmic = ctx.create_gain()?;
+        algorithmic.gain().set_value_at_time(0.0, ctx.current_time())?;
+        pre.connect_with_audio_node(&freeverb.input)?;
+        freeverb.output.connect_with_audio_node(&algorithmic)?;
+        algorithmic.connect_with_audio_node(&output)?;
+
+        let patch = ReverbPatch::default();
+        let mut state = State {
+            ctx,
+            mode: patch.mode,
+            decay: patch.decay,
+            pre_delay: patch.pre_delay,
+            size: patch.size,
+            damping: patch.damping,
+            pre,
+            convolvers,
+            convolver_gains,
+            active: 0,
+            current: None,
+            swapped_at: 0.0,
+            loaded: None,
+            convolution,
+            freeverb,
+            algorithmic,
+        };
+        state.update();
+        if let Some(impulse) = state.wanted() {
+            let now = state.ctx.current_time();
+            state.swap(impulse, now)?;
+        }
+        let state = Rc::new(RefCell::new(state));
+
+        let params: [Param; 5] = [
+            ("mode", patch.mode as u8 as f32, |s, v| s.mode = ReverbMode::from_value(v)),
+            ("decay", patch.decay, |s, v| s.decay = v.clamp(0.1, MAX_DECAY)),
+            ("pre_delay", patch.pre_delay, |s, v| s.pre_delay = v),
+            ("size", patch.size, |s, v| s.size = v.clamp(0.0, 1.0)),
+            ("damping", patch.damping, |s, v| s.damping = v.max(20.0)),
+        ];
+        for (id, initial, apply) in params.iter().cloned() {
+            let s = state.clone();
+            bus.control(format!("{}.{}", name, id), initial, Box::new(move |v| {
+                apply(&mut s.borrow_mut(), v);
+                s.borrow().update();
+            }));
+        }
+
+        Ok(Reverb {
+            name,
+            input,
+            output,
+            state,
+        })
+    }
+
+    /// Sets the impulse response convolved with in `Loaded` mode. It isn't saved with the patch.
+    pub fn load_impulse(&self, buffer: AudioBuffer) -> Result<(), JsValue> {
+        match buffer.number_of_channels() {
+            1 | 2 | 4 => {}
+            n => return Err(format!("Impulse responses need 1, 2 or 4 channels, not {}", n).into()),
+        }
+        let mut state = self.state.borrow_mut();
+        // The convolver would only throw once swapped to it, on tick
+        let sample_rate = state.ctx.sample_rate();
+        if buffer.sample_rate() != sample_rate {
+            return Err(format!("Impulse responses need to be at {}Hz, not {}Hz", sample_rate, buffer.sample_rate()).into());
+        }
+        state.loaded = Some(buffer);
+        // Swap even if the last one was loaded too
+        if state.current == Some(Impulse::Loaded) {
+            state.current = None;
+        }
+        Ok(())
+    }
+}
+
+impl AudioInput for Reverb {
+    fn input(&self) -> AudioNode {
+        self.input.clone().into()
+    }
+}
+
+impl AudioOutput for Reverb {
+    fn output(&self) -> AudioNode {
+        self.output.clone().into()
+    }
+}
+
+impl Effect for Reverb {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn default_mix(&self) -> f32 {
+        DEFAULT_MIX
+    }
+
+    fn tick(&self, now: f64) -> Result<(), JsValue> {
+        let mut state = self.state.borrow_mut();
+        match state.wanted() {
+            Some(impulse) if state.current != Some(impulse) && now - state.swapped_at >= SWAP.time => {
+                state.swap(impulse, now)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Drop for Reverb {
+    fn drop(&mut self) {
+        let bus = unsafe { get_bus() };
+        bus.remove(&format!("{}.", self.name));
+        // The combs and allpasses' feedback loops would otherwise keep each other alive
+        let state = self.state.borrow();
+        state.pre.disconnect().unwrap_or(());
+        for comb in &state.freeverb.combs {
+            comb.delay.disconnect().unwrap_or(());
+        }
+        for allpass in &state.freeverb.allpasses {
+            allpass.disconnect().unwrap_or(());
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use web_sys::AudioParam;
+
+/// The curve a parameter follows to its new value
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Ramp {
+    Linear,
+    /// Even steps in ratio rather than difference, for frequencies. Falls back to linear
+    /// from or to values which aren't positive.
+    Exponential,
+}
+
+/// How a kind of parameter glides to the values it's set to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Smoothing {
+    pub ramp: Ramp,
+    /// In seconds
+    pub time: f64,
+}
+
+/// Gains and levels, 0 to 1
+pub const GAIN: Smoothing = Smoothing { ramp: Ramp::Linear, time: 0.02 };
+/// Frequencies, in Hz
+pub const FREQUENCY: Smoothing = Smoothing { ramp: Ramp::Exponential, time: 0.03 };
+/// Filter Q and shelf or peak gains
+pub const Q: Smoothing = Smoothing { ramp: Ramp::Linear, time: 0.03 };
+/// Frequency ratios, like a synced oscillator's
+pub const RATIO: Smoothing = Smoothing { ramp: Ramp::Exponential, time: 0.03 };
+/// Compressor settings
+pub const DYNAMICS: Smoothing = Smoothing { ramp: Ramp::Linear, time: 0.05 };
+/// Modulation pushed every frame, e.g. by the mod matrix, finishing its ramp within a frame
+pub const MODULATION: Smoothing = Smoothing { ramp: Ramp::Linear, time: 0.01 };
+/// Delay times, in seconds. Slow enough for changes to bend the pitch rather than click.
+pub const TIME: Smoothing = Smoothing { ramp: Ramp::Linear, time: 0.1 };
+
+/// Moves `param` from wherever it is at `now` to `value`, following `smoothing`.
+///
+/// Cancels whatever was scheduled on `param`, so it isn't meant for params
+/// driven by envelopes.
+pub fn set(param: &AudioParam, value: f32, now: f64, smoothing: Smoothing) {
+    let from = param.value();
+    let end = now + smoothing.time;
+    param.cancel_scheduled_values(now).unwrap();
+    param.set_value_at_time(from, now).unwrap();
+    match smoothing.ramp {
+        Ramp::Exponential if from > 0.0 && value > 0.0 => param.exponential_ramp_to_value_at_time(value, end).unwrap(),
+        _ => param.linear_ramp_to_value_at_time(value, end).unwrap(),
+    };
+}
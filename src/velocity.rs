@@ -0,0 +1,99 @@
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+use crate::envelope::Envelope;
+
+/// How incoming note velocities map onto the 0 to 1 level driving each destination
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum VelocityCurve {
+    Linear = 0,
+    /// Reaches high levels with little effort, for stiff keyboards
+    Soft = 1,
+    /// Needs hitting harder to reach high levels, for light keyboards
+    Hard = 2,
+    /// Every note plays at the fixed level
+    Fixed = 3,
+    /// Interpolates the custom table
+    Custom = 4,
+}
+
+/// Maps `amount` (0 to 1) of `level` onto a multiplier, 1 when insensitive.
+pub fn sensitivity(amount: f32, level: f32) -> f32 {
+    1.0 - amount + amount * level
+}
+
+/// Velocity curve and how much each destination responds to it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Velocity {
+    pub curve: VelocityCurve,
+    /// Levels for velocities evenly spread from 0 to 127, used by `VelocityCurve::Custom`
+    pub table: Vec<f32>,
+    /// Level used by `VelocityCurve::Fixed`
+    pub fixed: f32,
+    /// How much (0 to 1) velocity scales the peak gain
+    pub amp: f32,
+    /// How much (-1 to 1) velocity shortens the attack and decay stages. Negative values lengthen them.
+    pub env_time: f32,
+    /// How much (0 to 1) velocity scales how far a hard-synced oscillator's ratio is from 1,
+    /// standing in for an FM index as it sets how bright sync sounds
+    pub fm: f32,
+}
+
+impl Default for Velocity {
+    fn default() -> Velocity {
+        Velocity {
+            curve: VelocityCurve::Linear,
+            table: vec![],
+            fixed: 1.0,
+            amp: 1.0,
+            env_time: 0.0,
+            fm: 0.0,
+        }
+    }
+}
+
+impl Velocity {
+    /// Where (0 to 1) `velocity` lands on the curve
+    pub fn level(&self, velocity: u8) -> f32 {
+        let v = velocity.min(127) as f32 / 127.0;
+        match self.curve {
+            VelocityCurve::Linear => v,
+            VelocityCurve::Soft => v.sqrt(),
+            VelocityCurve::Hard => v * v,
+            VelocityCurve::Fixed => self.fixed,
+            VelocityCurve::Custom => self.lookup(v),
+        }
+    }
+
+    /// Linearly interpolates the custom table at `v`, falling back to linear without one
+    fn lookup(&self, v: f32) -> f32 {
+        if self.table.len() < 2 {
+            return v;
+        }
+        let pos = v * (self.table.len() - 1) as f32;
+        let idx = (pos as usize).min(self.table.len() - 2);
+        let frac = pos - idx as f32;
+        self.table[idx] + (self.table[idx + 1] - self.table[idx]) * frac
+    }
+
+    /// Peak gain multiplier at `level`
+    pub fn amp(&self, level: f32) -> f32 {
+        sensitivity(self.amp, level)
+    }
+
+    /// Ratio of a hard-synced oscillator at `level`, `ratio` being the one set
+    pub fn sync_ratio(&self, ratio: f32, level: f32) -> f32 {
+        1.0 + (ratio - 1.0) * sensitivity(self.fm, level)
+    }
+
+    /// `env` with its attack and decay times scaled for `level`
+    pub fn envelope(&self, env: &Envelope, level: f32) -> Envelope {
+        let factor = (1.0 - self.env_time * level).max(0.0);
+        let mut env = env.clone();
+        env.attack *= factor;
+        env.decay *= factor;
+        env
+    }
+}
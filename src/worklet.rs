@@ -0,0 +1,68 @@
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::{AudioContext, AudioNode, AudioParam, AudioWorkletNode, Blob, BlobPropertyBag, OscillatorType, Url};
+
+const SYNC_OSCILLATOR: &str = include_str!("worklets/sync_oscillator.js");
+
+/// Registers the AudioWorklet processors used by the modules.
+///
+/// Worklet-backed nodes can only be created once the returned promise resolves.
+#[wasm_bindgen]
+pub fn load_worklets(ctx: &AudioContext) -> Result<js_sys::Promise, JsValue> {
+    let sources = js_sys::Array::of1(&SYNC_OSCILLATOR.into());
+    let mut options = BlobPropertyBag::new();
+    options.type_("application/javascript");
+    let blob = Blob::new_with_str_sequence_and_options(&sources, &options)?;
+    let url = Url::create_object_url_with_blob(&blob)?;
+    ctx.audio_worklet()?.add_module(&url)
+}
+
+fn param(node: &AudioWorkletNode, name: &str) -> AudioParam {
+    let params: js_sys::Map = node.parameters().unwrap().unchecked_into();
+    params.get(&name.into()).unchecked_into()
+}
+
+/// An oscillator whose phase is reset on every cycle of a master running at `frequency`.
+///
+/// The oscillator itself runs at `frequency * ratio`.
+pub struct SyncOscillator {
+    node: AudioWorkletNode,
+}
+
+impl SyncOscillator {
+    pub fn new(ctx: &AudioContext) -> Result<SyncOscillator, JsValue> {
+        let node = AudioWorkletNode::new(ctx, "sync-oscillator")?;
+        Ok(SyncOscillator { node })
+    }
+
+    pub fn frequency(&self) -> AudioParam {
+        param(&self.node, "frequency")
+    }
+
+    pub fn detune(&self) -> AudioParam {
+        param(&self.node, "detune")
+    }
+
+    pub fn ratio(&self) -> AudioParam {
+        param(&self.node, "ratio")
+    }
+
+    pub fn set_type(&self, waveform: OscillatorType) {
+        let shape = match waveform {
+            OscillatorType::Square => 1.0,
+            OscillatorType::Sawtooth => 2.0,
+            OscillatorType::Triangle => 3.0,
+            _ => 0.0,
+        };
+        param(&self.node, "shape").set_value(shape);
+    }
+
+    /// Stops the oscillator for good, letting the node be garbage collected
+    pub fn stop(&self) -> Result<(), JsValue> {
+        self.node.port()?.post_message(&"stop".into())
+    }
+
+    pub fn node(&self) -> AudioNode {
+        self.node.clone().into()
+    }
+}
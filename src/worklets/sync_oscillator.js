@@ -0,0 +1,84 @@
+// Slave oscillator hard-synced to an implicit master running at `frequency`.
+//
+// The slave runs at `frequency * ratio` and its phase is reset every time the
+// master completes a cycle, which gives the classic sync sweep when `ratio`
+// is modulated.
+const SHAPE_SINE = 0;
+const SHAPE_SQUARE = 1;
+const SHAPE_SAWTOOTH = 2;
+const SHAPE_TRIANGLE = 3;
+
+function waveform(shape, phase) {
+  switch (shape) {
+    case SHAPE_SQUARE:
+      return phase < 0.5 ? 1.0 : -1.0;
+    case SHAPE_SAWTOOTH:
+      return 2.0 * phase - 1.0;
+    case SHAPE_TRIANGLE:
+      return 1.0 - 4.0 * Math.abs(phase - 0.5);
+    case SHAPE_SINE:
+    default:
+      return Math.sin(2.0 * Math.PI * phase);
+  }
+}
+
+class SyncOscillatorProcessor extends AudioWorkletProcessor {
+  static get parameterDescriptors() {
+    return [
+      { name: 'frequency', defaultValue: 440, minValue: 0, maxValue: 20000, automationRate: 'a-rate' },
+      { name: 'detune', defaultValue: 0, minValue: -1200, maxValue: 1200, automationRate: 'k-rate' },
+      { name: 'ratio', defaultValue: 1, minValue: 1, maxValue: 16, automationRate: 'a-rate' },
+      { name: 'shape', defaultValue: SHAPE_SAWTOOTH, minValue: 0, maxValue: 3, automationRate: 'k-rate' },
+    ];
+  }
+
+  constructor() {
+    super();
+    this.master = 0.0;
+    this.slave = 0.0;
+    this.stopped = false;
+    this.port.onmessage = (event) => {
+      if (event.data === 'stop') {
+        this.stopped = true;
+      }
+    };
+  }
+
+  process(inputs, outputs, parameters) {
+    if (this.stopped) {
+      return false;
+    }
+    const output = outputs[0];
+    const channel = output[0];
+    const frequency = parameters.frequency;
+    const ratio = parameters.ratio;
+    const detune = Math.pow(2, parameters.detune[0] / 1200);
+    const shape = Math.round(parameters.shape[0]);
+
+    for (let i = 0; i < channel.length; i++) {
+      const f = (frequency.length > 1 ? frequency[i] : frequency[0]) * detune;
+      const r = ratio.length > 1 ? ratio[i] : ratio[0];
+      const increment = f / sampleRate;
+
+      this.master += increment;
+      if (this.master >= 1.0) {
+        // Reset the slave, keeping the fraction of the sample past the reset
+        this.master -= Math.floor(this.master);
+        this.slave = this.master * r;
+      } else {
+        this.slave += increment * r;
+      }
+      this.slave -= Math.floor(this.slave);
+
+      channel[i] = waveform(shape, this.slave);
+    }
+
+    for (let c = 1; c < output.length; c++) {
+      output[c].set(channel);
+    }
+
+    return true;
+  }
+}
+
+registerProcessor('sync-oscillator', SyncOscillatorProcessor);
@@ -41,6 +41,19 @@ import('./../pkg/jam')
         case 128:
           vue.$children[0].noteOff(note, velocity);
           break;
+        case 160:
+          vue.$children[0].polyAftertouch(note, velocity);
+          break;
+        case 208:
+          // Channel pressure only has one data byte
+          vue.$children[0].aftertouch(data[1]);
+          break;
+        case 176:
+          // Control change, the mod wheel being controller 1
+          if (note == 1) {
+            vue.$children[0].modWheel(velocity);
+          }
+          break;
       }
     };
 